@@ -0,0 +1,333 @@
+//! The document tree produced by the parser.
+
+pub use crate::position::{Position, Span};
+
+/// A parsed markdown document.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    pub blocks: Vec<Block>,
+    /// the referenced footnotes, in the order of their numbers
+    pub footnotes: Vec<Footnote>,
+    pub span: Span,
+}
+
+/// A block-level element.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    PlainParagraph(PlainParagraph),
+    Heading(Heading),
+    ThematicBreak(ThematicBreak),
+    OrderedList(OrderedList),
+    UnorderedList(UnorderedList),
+    Quote(Quote),
+    CodeBlock(CodeBlock),
+    HtmlBlock(HtmlBlock),
+    Table(Table),
+    MathDisplay(MathDisplay),
+}
+
+impl Block {
+    /// Where in the source this block came from.
+    pub fn span(&self) -> Span {
+        match self {
+            Block::PlainParagraph(x) => x.span,
+            Block::Heading(x) => x.span,
+            Block::ThematicBreak(x) => x.span,
+            Block::OrderedList(x) => x.span,
+            Block::UnorderedList(x) => x.span,
+            Block::Quote(x) => x.span,
+            Block::CodeBlock(x) => x.span,
+            Block::HtmlBlock(x) => x.span,
+            Block::Table(x) => x.span,
+            Block::MathDisplay(x) => x.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlainParagraph {
+    pub text: Text,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Heading {
+    /// 1 for `#` or a `===` underline, up to 6 for `######`
+    pub rank: u8,
+    pub style: HeadingStyle,
+    pub text: Text,
+    pub span: Span,
+}
+
+/// How a heading was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeadingStyle {
+    /// `# Heading`
+    #[default]
+    Atx,
+    /// a paragraph underlined with `===` or `---`
+    Setext,
+}
+
+/// `***`, `---` or `___` on a line of its own.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ThematicBreak {
+    /// the character the break is made of: `*`, `-` or `_`
+    pub marker: char,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OrderedList {
+    /// the number of the first item
+    pub start: u32,
+    /// `.` or `)`, the same for every item
+    pub delimiter: char,
+    /// whether the items are rendered without paragraphs
+    pub tight: bool,
+    pub items: Vec<ListItem>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UnorderedList {
+    /// `-`, `+`, `*` or, with [`crate::Options::equals_bullet`], `=`; the
+    /// same for every item
+    pub bullet: char,
+    /// whether the items are rendered without paragraphs
+    pub tight: bool,
+    pub items: Vec<ListItem>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ListItem {
+    /// `Some` for a task list item, telling whether it is checked
+    pub checked: Option<bool>,
+    pub blocks: Vec<Block>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Quote {
+    pub blocks: Vec<Block>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeBlock {
+    pub kind: CodeBlockKind,
+    /// the info string up to the first space or `{`, empty when there is
+    /// none
+    pub language: String,
+    /// the `key="value"` or `key=value` pairs after the language, in
+    /// order; a lone `key` has an empty value
+    pub attributes: Vec<(String, String)>,
+    /// the lines to highlight from a `{1,3-5}` after the language, as
+    /// inclusive ranges of line numbers counted from 1
+    pub highlights: Vec<(usize, usize)>,
+    pub text: String,
+    pub span: Span,
+}
+
+/// How a code block was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeBlockKind {
+    /// indented by four columns
+    #[default]
+    Indented,
+    /// between ```` ``` ```` or `~~~` fences
+    Fenced,
+}
+
+/// Raw HTML standing on its own lines.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HtmlBlock {
+    pub html: String,
+    pub span: Span,
+}
+
+/// A GFM pipe table.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Table {
+    /// one per column
+    pub alignments: Vec<Alignment>,
+    pub header: TableRow,
+    pub rows: Vec<TableRow>,
+    pub span: Span,
+}
+
+/// The alignment a table column asks for in its delimiter row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// `---`
+    None,
+    /// `:--`
+    Left,
+    /// `:-:`
+    Center,
+    /// `--:`
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TableRow {
+    /// exactly one per column
+    pub cells: Vec<Text>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MathDisplay {
+    pub formula: String,
+    pub span: Span,
+}
+
+/// A footnote, rendered after the rest of the document.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Footnote {
+    /// counted from 1, in the order the footnotes are first referenced
+    pub number: usize,
+    /// the label of a `[^label]: ...` definition, empty for an inline
+    /// `^[...]` footnote
+    pub label: String,
+    pub blocks: Vec<Block>,
+    /// how many times the footnote is referenced
+    pub references: usize,
+    pub span: Span,
+}
+
+/// A run of inline elements.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Text {
+    pub nodes: Vec<Inline>,
+    pub span: Span,
+}
+
+impl Text {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+/// An inline element.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Plain(Plain),
+    Bold(Bold),
+    Italic(Italic),
+    Deleted(Deleted),
+    CodeInline(CodeInline),
+    MathInline(MathInline),
+    Image(Image),
+    Url(Url),
+    HtmlInline(HtmlInline),
+    SoftBreak(SoftBreak),
+    HardBreak(HardBreak),
+    FootnoteReference(FootnoteReference),
+}
+
+impl Inline {
+    /// Where in the source this element came from.
+    pub fn span(&self) -> Span {
+        match self {
+            Inline::Plain(x) => x.span,
+            Inline::Bold(x) => x.span,
+            Inline::Italic(x) => x.span,
+            Inline::Deleted(x) => x.span,
+            Inline::CodeInline(x) => x.span,
+            Inline::MathInline(x) => x.span,
+            Inline::Image(x) => x.span,
+            Inline::Url(x) => x.span,
+            Inline::HtmlInline(x) => x.span,
+            Inline::SoftBreak(x) => x.span,
+            Inline::HardBreak(x) => x.span,
+            Inline::FootnoteReference(x) => x.span,
+        }
+    }
+}
+
+/// Characters without any markup.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Plain {
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bold {
+    pub text: Text,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Italic {
+    pub text: Text,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Deleted {
+    pub text: Text,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeInline {
+    pub code: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MathInline {
+    pub formula: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Image {
+    /// the description with its markup stripped
+    pub alt: String,
+    pub url: String,
+    /// empty when the image has no title
+    pub title: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Url {
+    pub text: Text,
+    pub url: String,
+    /// empty when the link has no title
+    pub title: String,
+    pub span: Span,
+}
+
+/// A raw HTML tag, comment or declaration inside text.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HtmlInline {
+    pub html: String,
+    pub span: Span,
+}
+
+/// A line ending inside a paragraph.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SoftBreak {
+    pub span: Span,
+}
+
+/// A line ending after two spaces or a backslash, rendered as `<br />`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HardBreak {
+    pub span: Span,
+}
+
+/// A `[^label]` or an inline `^[...]` footnote.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FootnoteReference {
+    /// the number of the footnote referenced
+    pub number: usize,
+    /// 1 for the first reference to the footnote, 2 for the second, ...
+    pub index: usize,
+    pub span: Span,
+}
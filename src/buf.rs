@@ -0,0 +1,29 @@
+pub type InnerByte = u8;
+pub type InnerBuffer = Vec<InnerByte>;
+
+/// Output sink the document is written into.
+pub trait Buf {
+    fn push_vec(&mut self, s: &InnerBuffer);
+    fn push_str(&mut self, s: &str);
+    fn push_char(&mut self, ch: char);
+    fn push(&mut self, ch: InnerByte);
+}
+
+impl Buf for Vec<u8> {
+    fn push_vec(&mut self, s: &InnerBuffer) {
+        self.extend_from_slice(s);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.extend_from_slice(s.as_bytes());
+    }
+
+    fn push_char(&mut self, ch: char) {
+        let mut tmp = [0; 4];
+        self.push_str(ch.encode_utf8(&mut tmp));
+    }
+
+    fn push(&mut self, ch: u8) {
+        Vec::push(self, ch);
+    }
+}
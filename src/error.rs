@@ -0,0 +1,36 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::position::Position;
+
+/// Why parsing a document failed.
+///
+/// Like CommonMark itself the parser accepts any text, so only reading the
+/// input can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The input given as bytes is not valid UTF-8 from `at` on.
+    InvalidUtf8 { at: Position },
+}
+
+impl ParseError {
+    /// Where in the source the error occurred.
+    pub fn position(&self) -> Position {
+        match self {
+            ParseError::InvalidUtf8 { at } => *at,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self.position();
+        match self {
+            ParseError::InvalidUtf8 { .. } => write!(f, "invalid UTF-8")?,
+        }
+        write!(f, " at line {}, column {}", at.line, at.column)
+    }
+}
+
+impl Error for ParseError {}
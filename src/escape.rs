@@ -0,0 +1,65 @@
+//! Escaping of text written into HTML.
+
+use crate::buf::Buf;
+
+/// Writes `s` escaped for HTML text content and double-quoted attribute
+/// values.
+pub fn escape_html(s: &str, buf: &mut dyn Buf) {
+    let mut last = 0;
+    for (i, c) in s.bytes().enumerate() {
+        let replacement = match c {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'"' => "&quot;",
+            _ => continue,
+        };
+        buf.push_str(&s[last..i]);
+        buf.push_str(replacement);
+        last = i + 1;
+    }
+    buf.push_str(&s[last..]);
+}
+
+/// Writes the url `s` for an `href` or `src` attribute.
+///
+/// Characters that may appear in a url as they are, including `%` so that
+/// already encoded urls stay intact, are kept; `&` and `'` become entities
+/// and everything else is percent-encoded.
+pub fn escape_href(s: &str, buf: &mut dyn Buf) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for &c in s.as_bytes() {
+        match c {
+            b'&' => buf.push_str("&amp;"),
+            b'\'' => buf.push_str("&#x27;"),
+            _ if c.is_ascii_alphanumeric() || b"-_.+!*(),%#@?=;:/$~".contains(&c) => buf.push(c),
+            _ => {
+                buf.push(b'%');
+                buf.push(HEX[(c >> 4) as usize]);
+                buf.push(HEX[(c & 0xf) as usize]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(f: fn(&str, &mut dyn Buf), s: &str) -> String {
+        let mut buf: Vec<u8> = vec![];
+        f(s, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(escaped(escape_html, "a<b & \"c\">ü"), "a&lt;b &amp; &quot;c&quot;&gt;ü");
+    }
+
+    #[test]
+    fn escapes_href() {
+        assert_eq!(escaped(escape_href, "/a b?x=1&y='ä'%20"), "/a%20b?x=1&amp;y=&#x27;%C3%A4&#x27;%20");
+        assert_eq!(escaped(escape_href, "\"><script>"), "%22%3E%3Cscript%3E");
+    }
+}
@@ -0,0 +1,356 @@
+//! The HTML every [`Renderer`] hook produces by default.
+
+use crate::ast::*;
+use crate::buf::Buf;
+use crate::escape::{escape_href, escape_html};
+use crate::options::{Options, RawHtml, SoftBreakStyle};
+use crate::render::Renderer;
+use crate::sanitize::sanitize_html;
+
+/// Tags GFM escapes in raw HTML because they change how the rest of the
+/// page is parsed.
+const FILTERED_TAGS: &[&str] = &[
+    "title", "textarea", "style", "xmp", "iframe", "noembed", "noframes", "script", "plaintext",
+];
+
+/// Renders documents as HTML using the default of every hook.
+#[derive(Debug, Clone, Default)]
+pub struct HtmlRenderer {
+    options: Options,
+}
+
+impl HtmlRenderer {
+    pub fn new(options: &Options) -> Self {
+        HtmlRenderer { options: options.clone() }
+    }
+}
+
+impl Renderer for HtmlRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+}
+
+/// Writes `url` unless safe mode rejects it.
+fn write_url<R: Renderer + ?Sized>(r: &mut R, url: &str, buf: &mut dyn Buf) {
+    match &r.options().safe_mode {
+        Some(safe) if !safe.is_safe_url(url) => {}
+        _ => escape_href(url, buf),
+    }
+}
+
+/// Writes raw HTML as [`Options::raw_html`] asks, sanitized in safe mode
+/// and with the GFM tag filter applied if enabled.
+fn write_raw_html<R: Renderer + ?Sized>(r: &mut R, html: &str, buf: &mut dyn Buf) {
+    let options = r.options();
+    if options.raw_html == RawHtml::Escape {
+        escape_html(html, buf);
+    } else if let Some(safe) = &options.safe_mode {
+        sanitize_html(html, safe, buf);
+    } else if options.tag_filter {
+        filter_tags(html, buf);
+    } else {
+        buf.push_str(html);
+    }
+}
+
+/// Writes `html` with the `<` of every filtered tag escaped.
+fn filter_tags(html: &str, buf: &mut dyn Buf) {
+    let bytes = html.as_bytes();
+    let mut last = 0;
+    for (i, _) in html.match_indices('<') {
+        let name_start = if bytes.get(i + 1) == Some(&b'/') { i + 2 } else { i + 1 };
+        let filtered = FILTERED_TAGS.iter().any(|tag| {
+            let name_end = name_start + tag.len();
+            bytes.len() >= name_end
+                && bytes[name_start..name_end].eq_ignore_ascii_case(tag.as_bytes())
+                && match bytes.get(name_end) {
+                    None => false,
+                    Some(b'>') => true,
+                    Some(b'/') => bytes.get(name_end + 1) == Some(&b'>'),
+                    Some(c) => c.is_ascii_whitespace(),
+                }
+        });
+        if filtered {
+            buf.push_str(&html[last..i]);
+            buf.push_str("&lt;");
+            last = i + 1;
+        }
+    }
+    buf.push_str(&html[last..]);
+}
+
+pub(crate) fn document<R: Renderer + ?Sized>(r: &mut R, node: &Document, buf: &mut dyn Buf) {
+    node.blocks.iter().for_each(|x| r.render_block(x, buf));
+    if !node.footnotes.is_empty() {
+        r.render_footnotes(&node.footnotes, buf);
+    }
+}
+
+pub(crate) fn plain_paragraph<R: Renderer + ?Sized>(r: &mut R, node: &PlainParagraph, buf: &mut dyn Buf) {
+    buf.push_str("<p>");
+    r.render_text(&node.text, buf);
+    buf.push_str("</p>\n");
+}
+
+pub(crate) fn heading<R: Renderer + ?Sized>(r: &mut R, node: &Heading, buf: &mut dyn Buf) {
+    buf.push_str(&format!("<h{}>", node.rank));
+    r.render_text(&node.text, buf);
+    buf.push_str(&format!("</h{}>\n", node.rank));
+}
+
+pub(crate) fn thematic_break<R: Renderer + ?Sized>(_: &mut R, _: &ThematicBreak, buf: &mut dyn Buf) {
+    buf.push_str("<hr />\n");
+}
+
+pub(crate) fn ordered_list<R: Renderer + ?Sized>(r: &mut R, node: &OrderedList, buf: &mut dyn Buf) {
+    if node.start == 1 {
+        buf.push_str("<ol>\n");
+    } else {
+        buf.push_str(&format!("<ol start=\"{}\">\n", node.start));
+    }
+    node.items.iter().for_each(|x| r.render_list_item(x, node.tight, buf));
+    buf.push_str("</ol>\n");
+}
+
+pub(crate) fn unordered_list<R: Renderer + ?Sized>(r: &mut R, node: &UnorderedList, buf: &mut dyn Buf) {
+    buf.push_str("<ul>\n");
+    node.items.iter().for_each(|x| r.render_list_item(x, node.tight, buf));
+    buf.push_str("</ul>\n");
+}
+
+pub(crate) fn list_item<R: Renderer + ?Sized>(r: &mut R, node: &ListItem, tight: bool, buf: &mut dyn Buf) {
+    buf.push_str("<li>");
+    match node.checked {
+        Some(true) => buf.push_str("<input checked=\"\" disabled=\"\" type=\"checkbox\"> "),
+        Some(false) => buf.push_str("<input disabled=\"\" type=\"checkbox\"> "),
+        None => {}
+    }
+    // blocks end with a line break, tight paragraphs don't
+    let mut line_start = false;
+    for x in &node.blocks {
+        match x {
+            Block::PlainParagraph(p) if tight => {
+                r.render_text(&p.text, buf);
+                line_start = false;
+            }
+            _ => {
+                if !line_start {
+                    buf.push_char('\n');
+                }
+                r.render_block(x, buf);
+                line_start = true;
+            }
+        }
+    }
+    buf.push_str("</li>\n");
+}
+
+pub(crate) fn quote<R: Renderer + ?Sized>(r: &mut R, node: &Quote, buf: &mut dyn Buf) {
+    buf.push_str("<blockquote>\n");
+    node.blocks.iter().for_each(|x| r.render_block(x, buf));
+    buf.push_str("</blockquote>\n");
+}
+
+pub(crate) fn code_block<R: Renderer + ?Sized>(_: &mut R, node: &CodeBlock, buf: &mut dyn Buf) {
+    buf.push_str("<pre><code");
+    if !node.language.is_empty() {
+        buf.push_str(" class=\"language-");
+        escape_html(&node.language, buf);
+        buf.push_str("\"");
+    }
+    buf.push_str(">");
+    escape_html(&node.text, buf);
+    buf.push_str("</code></pre>\n");
+}
+
+pub(crate) fn html_block<R: Renderer + ?Sized>(r: &mut R, node: &HtmlBlock, buf: &mut dyn Buf) {
+    match r.options().raw_html {
+        RawHtml::Drop => {}
+        RawHtml::Escape => {
+            buf.push_str("<p>");
+            escape_html(&node.html, buf);
+            buf.push_str("</p>\n");
+        }
+        RawHtml::Pass => {
+            write_raw_html(r, &node.html, buf);
+            buf.push_char('\n');
+        }
+    }
+}
+
+fn table_row<R: Renderer + ?Sized>(r: &mut R, row: &TableRow, alignments: &[Alignment], tag: &str, buf: &mut dyn Buf) {
+    buf.push_str("<tr>\n");
+    for (cell, alignment) in row.cells.iter().zip(alignments) {
+        buf.push_str("<");
+        buf.push_str(tag);
+        match alignment {
+            Alignment::None => {}
+            Alignment::Left => buf.push_str(" align=\"left\""),
+            Alignment::Center => buf.push_str(" align=\"center\""),
+            Alignment::Right => buf.push_str(" align=\"right\""),
+        }
+        buf.push_str(">");
+        r.render_text(cell, buf);
+        buf.push_str("</");
+        buf.push_str(tag);
+        buf.push_str(">\n");
+    }
+    buf.push_str("</tr>\n");
+}
+
+pub(crate) fn table<R: Renderer + ?Sized>(r: &mut R, node: &Table, buf: &mut dyn Buf) {
+    buf.push_str("<table>\n<thead>\n");
+    table_row(r, &node.header, &node.alignments, "th", buf);
+    buf.push_str("</thead>\n");
+    if !node.rows.is_empty() {
+        buf.push_str("<tbody>\n");
+        for x in &node.rows {
+            table_row(r, x, &node.alignments, "td", buf);
+        }
+        buf.push_str("</tbody>\n");
+    }
+    buf.push_str("</table>\n");
+}
+
+pub(crate) fn math_display<R: Renderer + ?Sized>(_: &mut R, node: &MathDisplay, buf: &mut dyn Buf) {
+    buf.push_str("<pre class=\"lang-math-display\">");
+    escape_html(&node.formula, buf);
+    buf.push_str("</pre>\n");
+}
+
+pub(crate) fn footnotes<R: Renderer + ?Sized>(r: &mut R, nodes: &[Footnote], buf: &mut dyn Buf) {
+    buf.push_str("<section class=\"footnotes\">\n");
+    match nodes.first() {
+        Some(x) if x.number != 1 => buf.push_str(&format!("<ol start=\"{}\">\n", x.number)),
+        _ => buf.push_str("<ol>\n"),
+    }
+    nodes.iter().for_each(|x| r.render_footnote(x, buf));
+    buf.push_str("</ol>\n</section>\n");
+}
+
+/// The id of the `index`th reference to footnote `number`.
+fn footnote_reference_id(number: usize, index: usize) -> String {
+    if index == 1 {
+        format!("fnref-{}", number)
+    } else {
+        format!("fnref-{}-{}", number, index)
+    }
+}
+
+pub(crate) fn footnote<R: Renderer + ?Sized>(r: &mut R, node: &Footnote, buf: &mut dyn Buf) {
+    buf.push_str(&format!("<li id=\"fn-{}\">\n", node.number));
+    let mut backrefs = String::new();
+    for index in 1..=node.references {
+        backrefs.push_str(&format!(
+            " <a href=\"#{}\" class=\"footnote-backref\">\u{21a9}",
+            footnote_reference_id(node.number, index)
+        ));
+        if index > 1 {
+            backrefs.push_str(&format!("<sup>{}</sup>", index));
+        }
+        backrefs.push_str("</a>");
+    }
+    // the links back go at the end of the last paragraph if there is one
+    match node.blocks.split_last() {
+        Some((Block::PlainParagraph(last), rest)) => {
+            rest.iter().for_each(|x| r.render_block(x, buf));
+            buf.push_str("<p>");
+            r.render_text(&last.text, buf);
+            buf.push_str(&backrefs);
+            buf.push_str("</p>\n");
+        }
+        _ => {
+            node.blocks.iter().for_each(|x| r.render_block(x, buf));
+            buf.push_str("<p>");
+            buf.push_str(backrefs.trim_start());
+            buf.push_str("</p>\n");
+        }
+    }
+    buf.push_str("</li>\n");
+}
+
+pub(crate) fn plain<R: Renderer + ?Sized>(_: &mut R, node: &Plain, buf: &mut dyn Buf) {
+    escape_html(&node.text, buf);
+}
+
+pub(crate) fn bold<R: Renderer + ?Sized>(r: &mut R, node: &Bold, buf: &mut dyn Buf) {
+    buf.push_str("<strong>");
+    r.render_text(&node.text, buf);
+    buf.push_str("</strong>");
+}
+
+pub(crate) fn italic<R: Renderer + ?Sized>(r: &mut R, node: &Italic, buf: &mut dyn Buf) {
+    buf.push_str("<em>");
+    r.render_text(&node.text, buf);
+    buf.push_str("</em>");
+}
+
+pub(crate) fn deleted<R: Renderer + ?Sized>(r: &mut R, node: &Deleted, buf: &mut dyn Buf) {
+    buf.push_str("<del>");
+    r.render_text(&node.text, buf);
+    buf.push_str("</del>");
+}
+
+pub(crate) fn code_inline<R: Renderer + ?Sized>(_: &mut R, node: &CodeInline, buf: &mut dyn Buf) {
+    buf.push_str("<code>");
+    escape_html(&node.code, buf);
+    buf.push_str("</code>");
+}
+
+pub(crate) fn math_inline<R: Renderer + ?Sized>(_: &mut R, node: &MathInline, buf: &mut dyn Buf) {
+    buf.push_str("<div class=\"lang-math-inline\">");
+    escape_html(&node.formula, buf);
+    buf.push_str("</div>");
+}
+
+pub(crate) fn image<R: Renderer + ?Sized>(r: &mut R, node: &Image, buf: &mut dyn Buf) {
+    buf.push_str("<img src=\"");
+    write_url(r, &node.url, buf);
+    buf.push_str("\" alt=\"");
+    escape_html(&node.alt, buf);
+    if !node.title.is_empty() {
+        buf.push_str("\" title=\"");
+        escape_html(&node.title, buf);
+    }
+    buf.push_str("\" />");
+}
+
+pub(crate) fn url<R: Renderer + ?Sized>(r: &mut R, node: &Url, buf: &mut dyn Buf) {
+    buf.push_str("<a href=\"");
+    write_url(r, &node.url, buf);
+    if !node.title.is_empty() {
+        buf.push_str("\" title=\"");
+        escape_html(&node.title, buf);
+    }
+    buf.push_str("\">");
+    r.render_text(&node.text, buf);
+    buf.push_str("</a>");
+}
+
+pub(crate) fn html_inline<R: Renderer + ?Sized>(r: &mut R, node: &HtmlInline, buf: &mut dyn Buf) {
+    if r.options().raw_html != RawHtml::Drop {
+        write_raw_html(r, &node.html, buf);
+    }
+}
+
+pub(crate) fn soft_break<R: Renderer + ?Sized>(r: &mut R, _: &SoftBreak, buf: &mut dyn Buf) {
+    match r.options().soft_break {
+        SoftBreakStyle::Newline => buf.push_char('\n'),
+        SoftBreakStyle::Space => buf.push_char(' '),
+        SoftBreakStyle::HardBreak => buf.push_str("<br />\n"),
+    }
+}
+
+pub(crate) fn hard_break<R: Renderer + ?Sized>(_: &mut R, _: &HardBreak, buf: &mut dyn Buf) {
+    buf.push_str("<br />\n");
+}
+
+pub(crate) fn footnote_reference<R: Renderer + ?Sized>(_: &mut R, node: &FootnoteReference, buf: &mut dyn Buf) {
+    buf.push_str(&format!(
+        "<sup class=\"footnote-ref\"><a href=\"#fn-{}\" id=\"{}\">{}</a></sup>",
+        node.number,
+        footnote_reference_id(node.number, node.index),
+        node.number
+    ));
+}
@@ -0,0 +1,89 @@
+//! A markdown parser written in Rust.
+//!
+//! ```
+//! let html = markdowner::to_html("Hello *world*")?;
+//! assert_eq!(html, "<p>Hello <em>world</em></p>\n");
+//! # Ok::<(), markdowner::ParseError>(())
+//! ```
+
+pub mod ast;
+mod buf;
+mod entities;
+mod error;
+pub mod escape;
+mod html;
+mod options;
+mod parser;
+mod position;
+mod render;
+pub mod sanitize;
+pub mod tasks;
+pub mod visit;
+
+pub use crate::ast::Document;
+pub use crate::buf::{Buf, InnerBuffer, InnerByte};
+pub use crate::error::ParseError;
+pub use crate::html::HtmlRenderer;
+pub use crate::options::{Options, RawHtml, SoftBreakStyle};
+pub use crate::position::Position;
+pub use crate::render::Renderer;
+pub use crate::sanitize::SafeMode;
+
+use crate::position::LineIndex;
+
+impl Document {
+    /// Writes the document as HTML into `buf`, honoring the rendering
+    /// options such as [`Options::safe_mode`].
+    ///
+    /// A document does not remember the options it was parsed with, so pass
+    /// the same ones again to render it the way they ask for.
+    pub fn write_to_buf(&self, buf: &mut dyn Buf, options: &Options) {
+        HtmlRenderer::new(options).render_document(self, buf);
+    }
+
+    /// Renders the document as HTML.
+    pub fn to_html(&self) -> String {
+        self.to_html_with_options(&Options::default())
+    }
+
+    /// Renders the document as HTML, honoring the rendering options such as
+    /// [`Options::safe_mode`].
+    pub fn to_html_with_options(&self, options: &Options) -> String {
+        let mut buf: Vec<u8> = vec![];
+        self.write_to_buf(&mut buf, options);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+/// Parses `input` with the default options.
+pub fn parse(input: &str) -> Result<Document, ParseError> {
+    parse_with_options(input, &Options::default())
+}
+
+/// Parses `input` with the given options.
+///
+/// Never panics, and never fails either: like CommonMark itself the parser
+/// accepts any text. See [`parse_bytes_with_options`] for input that may not
+/// be text.
+pub fn parse_with_options(input: &str, options: &Options) -> Result<Document, ParseError> {
+    Ok(parser::parse(input, options))
+}
+
+/// Parses the UTF-8 encoded `input` with the given options, failing with
+/// [`ParseError::InvalidUtf8`] where it is not valid UTF-8.
+pub fn parse_bytes_with_options(input: &[u8], options: &Options) -> Result<Document, ParseError> {
+    match std::str::from_utf8(input) {
+        Ok(input) => parse_with_options(input, options),
+        Err(e) => Err(ParseError::InvalidUtf8 { at: LineIndex::new(input).position(e.valid_up_to()) }),
+    }
+}
+
+/// Converts `input` to HTML with the default options.
+pub fn to_html(input: &str) -> Result<String, ParseError> {
+    Ok(parse(input)?.to_html())
+}
+
+/// Converts `input` to HTML with the given options.
+pub fn to_html_with_options(input: &str, options: &Options) -> Result<String, ParseError> {
+    Ok(parse_with_options(input, options)?.to_html_with_options(options))
+}
@@ -1,26 +1,323 @@
-use std::io::{Read, Write};
+use std::env;
 use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
 
-mod parser;
-use crate::parser::Buf;
-use crate::parser::InnerBuffer;
-use crate::parser::InnerByte;
+use markdowner::ast::{CodeInline, Footnote, FootnoteReference, Heading, Plain, SoftBreak};
+use markdowner::visit::{walk_footnote_mut, Visitor, VisitorMut};
+use markdowner::{Document, Options, RawHtml, SafeMode, SoftBreakStyle};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-fn read_from_file(filename: &str) -> String {
-    let mut file = File::open(filename).unwrap();
-    let mut buf = String::new();
-    file.read_to_string(&mut buf).unwrap();
-    return buf
+const USAGE: &str = "\
+Usage: markdowner [OPTIONS] [FILE]...
+
+Converts markdown FILEs, or the standard input when none or `-` is given.
+
+Options:
+  -o, --output FILE    write to FILE instead of the standard output
+  -f, --format FORMAT  output format: html (default) or ast
+  -s, --standalone     wrap html output in a complete document
+      --safe           drop unsafe urls and raw HTML
+      --raw-html MODE  pass (default), escape or drop raw HTML
+      --soft-break AS  render line endings inside paragraphs as a newline
+                       (default), a space or a hard break (`br`)
+  -h, --help           print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Html,
+    Ast,
+}
+
+struct Args {
+    inputs: Vec<String>,
+    output: Option<String>,
+    format: Format,
+    standalone: bool,
+    safe: bool,
+    raw_html: RawHtml,
+    soft_break: SoftBreakStyle,
+}
+
+/// Why a run failed; usage errors exit with 2, everything else with 1.
+#[derive(Debug)]
+enum Failure {
+    Usage(String),
+    Io(String),
+    Parse(String),
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, Failure> {
+    let mut parsed = Args {
+        inputs: vec![],
+        output: None,
+        format: Format::Html,
+        standalone: false,
+        safe: false,
+        raw_html: RawHtml::Pass,
+        soft_break: SoftBreakStyle::Newline,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| Failure::Usage(format!("{} requires a value", arg)))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => parsed.output = Some(value()?),
+            "-f" | "--format" => {
+                parsed.format = match value()?.as_str() {
+                    "html" => Format::Html,
+                    "ast" => Format::Ast,
+                    x => return Err(Failure::Usage(format!("unknown format `{}`", x))),
+                }
+            }
+            "-s" | "--standalone" => parsed.standalone = true,
+            "--safe" => parsed.safe = true,
+            "--raw-html" => {
+                parsed.raw_html = match value()?.as_str() {
+                    "pass" => RawHtml::Pass,
+                    "escape" => RawHtml::Escape,
+                    "drop" => RawHtml::Drop,
+                    x => return Err(Failure::Usage(format!("unknown raw HTML mode `{}`", x))),
+                }
+            }
+            "--soft-break" => {
+                parsed.soft_break = match value()?.as_str() {
+                    "newline" => SoftBreakStyle::Newline,
+                    "space" => SoftBreakStyle::Space,
+                    "br" => SoftBreakStyle::HardBreak,
+                    x => return Err(Failure::Usage(format!("unknown soft break `{}`", x))),
+                }
+            }
+            x if x.starts_with('-') && x != "-" => {
+                return Err(Failure::Usage(format!("unknown option `{}`", x)))
+            }
+            _ => parsed.inputs.push(arg),
+        }
+    }
+    if parsed.standalone && parsed.format != Format::Html {
+        return Err(Failure::Usage("--standalone only applies to html".to_string()));
+    }
+    if parsed.inputs.is_empty() {
+        parsed.inputs.push("-".to_string());
+    }
+    Ok(Some(parsed))
+}
+
+fn read_from_file(filename: &str) -> io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    if filename == "-" {
+        io::stdin().read_to_end(&mut buf)?;
+    } else {
+        File::open(filename)?.read_to_end(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+/// Collects the text of inline elements without their markup.
+struct PlainText(String);
+
+impl Visitor for PlainText {
+    fn visit_plain(&mut self, node: &Plain) {
+        self.0.push_str(&node.text);
+    }
+
+    fn visit_code_inline(&mut self, node: &CodeInline) {
+        self.0.push_str(&node.code);
+    }
+
+    fn visit_soft_break(&mut self, _node: &SoftBreak) {
+        self.0.push(' ');
+    }
+}
+
+/// Collects the plain text of the first heading, for the page title.
+struct FirstHeading(Option<String>);
+
+impl Visitor for FirstHeading {
+    fn visit_heading(&mut self, node: &Heading) {
+        if self.0.is_none() {
+            let mut title = PlainText(String::new());
+            title.visit_text(&node.text);
+            self.0 = Some(title.0.trim().to_string());
+        }
+    }
+}
+
+/// Shifts the footnote numbers of a document, so that the documents of one
+/// page don't share footnote ids.
+struct RenumberFootnotes(usize);
+
+impl VisitorMut for RenumberFootnotes {
+    fn visit_footnote_mut(&mut self, node: &mut Footnote) {
+        node.number += self.0;
+        walk_footnote_mut(self, node)
+    }
+
+    fn visit_footnote_reference_mut(&mut self, node: &mut FootnoteReference) {
+        node.number += self.0;
+    }
+}
+
+/// Numbers the footnotes of documents rendered into one page in a single
+/// sequence.
+fn number_footnotes(docs: &mut [Document]) {
+    let mut count = 0;
+    for doc in docs {
+        RenumberFootnotes(count).visit_document_mut(doc);
+        count += doc.footnotes.len();
+    }
+}
+
+fn standalone(body: &str, docs: &[Document]) -> String {
+    let mut title = FirstHeading(None);
+    docs.iter().for_each(|x| title.visit_document(x));
+    let mut page: Vec<u8> = vec![];
+    page.extend_from_slice(b"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>");
+    markdowner::escape::escape_html(title.0.as_deref().unwrap_or(""), &mut page);
+    page.extend_from_slice(b"</title>\n</head>\n<body>\n");
+    page.extend_from_slice(body.as_bytes());
+    page.extend_from_slice(b"\n</body>\n</html>\n");
+    String::from_utf8_lossy(&page).into_owned()
+}
+
+fn run(args: &Args) -> Result<(), Failure> {
+    let options = Options {
+        safe_mode: if args.safe { Some(SafeMode::default()) } else { None },
+        raw_html: args.raw_html,
+        soft_break: args.soft_break,
+        ..Options::default()
+    };
+    let mut docs = vec![];
+    for input in &args.inputs {
+        let name = if input == "-" { "<stdin>" } else { input.as_str() };
+        let text = read_from_file(input)
+            .map_err(|e| Failure::Io(format!("cannot read {}: {}", name, e)))?;
+        let doc = markdowner::parse_bytes_with_options(&text, &options)
+            .map_err(|e| Failure::Parse(format!("{}: {}", name, e)))?;
+        docs.push(doc);
+    }
+    number_footnotes(&mut docs);
+
+    let mut output = String::new();
+    for doc in &docs {
+        match args.format {
+            Format::Html => output.push_str(&doc.to_html_with_options(&options)),
+            Format::Ast => output.push_str(&format!("{:#?}\n", doc)),
+        }
+    }
+    if args.standalone {
+        output = standalone(&output, &docs);
+    }
+
+    let written = match &args.output {
+        Some(path) => File::create(path).and_then(|mut f| f.write_all(output.as_bytes())),
+        None => io::stdout().write_all(output.as_bytes()),
+    };
+    let name = args.output.as_deref().unwrap_or("<stdout>");
+    written.map_err(|e| Failure::Io(format!("cannot write {}: {}", name, e)))
+}
+
+fn report(message: &str) {
+    // termcolor doesn't check whether stderr is a terminal
+    let color = if io::stderr().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never };
+    let mut stderr = StandardStream::stderr(color);
+    let _ = stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true));
+    let _ = write!(stderr, "error");
+    let _ = stderr.reset();
+    let _ = writeln!(stderr, ": {}", message);
 }
 
-include!("vec_u8_buf.in");
+fn exit_code(result: &Result<(), Failure>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(Failure::Usage(_)) => 2,
+        Err(Failure::Io(_)) | Err(Failure::Parse(_)) => 1,
+    }
+}
 
 fn main() {
-    let input = read_from_file("temp/input.txt");
-//    println!("Input: {}", input);
-    let mut buf: Vec<u8> = vec![];
-    parser::parse(&input.into_bytes(), &mut buf);
-    let mut output = File::create("temp/output.html").unwrap();
-    output.write(buf.as_slice()).unwrap();
-
-}
\ No newline at end of file
+    let result = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => run(&args),
+        Ok(None) => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    };
+    match &result {
+        Ok(()) => {}
+        Err(Failure::Usage(message)) => {
+            report(message);
+            eprintln!("\n{}", USAGE);
+        }
+        Err(Failure::Io(message)) | Err(Failure::Parse(message)) => report(message),
+    }
+    process::exit(exit_code(&result));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, Failure> {
+        parse_args(args.iter().map(|x| x.to_string()))
+    }
+
+    fn usage_error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(Failure::Usage(message)) => message,
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("{:?} should not parse", args),
+        }
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args = parse(&["a.md", "-f", "ast", "--raw-html", "drop", "-", "-o", "out"]).unwrap().unwrap();
+        assert_eq!(args.inputs, ["a.md", "-"]);
+        assert_eq!(args.output.as_deref(), Some("out"));
+        assert_eq!((args.format, args.raw_html), (Format::Ast, RawHtml::Drop));
+        assert!(!args.standalone && !args.safe);
+        assert_eq!(parse(&[]).unwrap().unwrap().inputs, ["-"]);
+        assert!(parse(&["a.md", "--help", "--bogus"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(usage_error(&["--bogus"]), "unknown option `--bogus`");
+        assert_eq!(usage_error(&["a.md", "-o"]), "-o requires a value");
+        assert_eq!(usage_error(&["--format", "pdf"]), "unknown format `pdf`");
+        assert_eq!(usage_error(&["-s", "-f", "ast"]), "--standalone only applies to html");
+    }
+
+    #[test]
+    fn maps_failures_to_exit_codes() {
+        assert_eq!(exit_code(&Ok(())), 0);
+        assert_eq!(exit_code(&Err(Failure::Io(String::new()))), 1);
+        assert_eq!(exit_code(&Err(Failure::Parse(String::new()))), 1);
+        assert_eq!(exit_code(&parse(&["--bogus"]).map(|_| ())), 2);
+        let missing = Args { inputs: vec!["/nonexistent/a.md".to_string()], ..parse(&[]).unwrap().unwrap() };
+        assert_eq!(exit_code(&run(&missing)), 1);
+    }
+
+    #[test]
+    fn titles_pages_with_the_text_of_the_first_heading() {
+        let doc = markdowner::parse("text\n\n# Hi *there* `x`\n## No\n").unwrap();
+        let page = standalone("", &[doc]);
+        assert!(page.contains("<title>Hi there x</title>"), "{}", page);
+    }
+
+    #[test]
+    fn numbers_footnotes_across_documents() {
+        let mut docs: Vec<_> = ["a[^x]\n\n[^x]: b\n", "c[^y]\n\n[^y]: d\n"]
+            .iter()
+            .map(|x| markdowner::parse(x).unwrap())
+            .collect();
+        number_footnotes(&mut docs);
+        let docs: Vec<_> = docs.iter().map(|x| x.to_html()).collect();
+        assert!(docs[0].contains("id=\"fn-1\"") && docs[0].contains("id=\"fnref-1\""), "{}", docs[0]);
+        assert!(docs[1].contains("id=\"fn-2\"") && docs[1].contains("id=\"fnref-2\""), "{}", docs[1]);
+    }
+}
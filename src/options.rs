@@ -0,0 +1,96 @@
+use crate::sanitize::SafeMode;
+
+/// How raw HTML is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawHtml {
+    /// written as is, subject to [`Options::tag_filter`] and
+    /// [`Options::safe_mode`]
+    #[default]
+    Pass,
+    /// written as text
+    Escape,
+    /// left out
+    Drop,
+}
+
+/// How a line ending inside a paragraph is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SoftBreakStyle {
+    /// kept as a line break, which browsers show as a space
+    #[default]
+    Newline,
+    Space,
+    /// like a hard break, as `<br />`
+    HardBreak,
+}
+
+/// Switches for parsing and rendering.
+///
+/// The default enables the GitHub Flavored Markdown extensions and math;
+/// [`Options::commonmark`] turns every extension off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// `~~deleted~~` text
+    pub strikethrough: bool,
+    /// `$inline$` and `$$display$$` formulas
+    pub math: bool,
+    /// GFM pipe tables
+    pub tables: bool,
+    /// `- [ ]` and `- [x]` list items
+    pub task_lists: bool,
+    /// bare `www.`, `http://` and email links
+    pub autolinks: bool,
+    /// `[^label]` references to `[^label]: ...` definitions, and inline
+    /// `^[...]` footnotes
+    pub footnotes: bool,
+    /// escaping raw HTML tags such as `<script>` and `<title>` the way GFM does
+    pub tag_filter: bool,
+    /// what becomes of raw HTML blocks and inline tags
+    pub raw_html: RawHtml,
+    /// how the line endings inside a paragraph are rendered
+    pub soft_break: SoftBreakStyle,
+    /// `=` as a bullet next to `-`, `+` and `*`; not part of any spec, so off
+    /// even by default
+    pub equals_bullet: bool,
+    /// allowlists enforced while rendering untrusted input, off by default
+    pub safe_mode: Option<SafeMode>,
+}
+
+pub(crate) static DEFAULT_OPTIONS: Options = Options {
+    strikethrough: true,
+    math: true,
+    tables: true,
+    task_lists: true,
+    autolinks: true,
+    footnotes: true,
+    tag_filter: true,
+    raw_html: RawHtml::Pass,
+    soft_break: SoftBreakStyle::Newline,
+    equals_bullet: false,
+    safe_mode: None,
+};
+
+impl Options {
+    /// Plain CommonMark, without any extension.
+    pub fn commonmark() -> Self {
+        Options {
+            strikethrough: false,
+            math: false,
+            tables: false,
+            task_lists: false,
+            autolinks: false,
+            footnotes: false,
+            tag_filter: false,
+            raw_html: RawHtml::Pass,
+            soft_break: SoftBreakStyle::Newline,
+            equals_bullet: false,
+            safe_mode: None,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        DEFAULT_OPTIONS.clone()
+    }
+}
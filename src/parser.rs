@@ -1,1039 +1,796 @@
-use std::any::{Any, TypeId};
-
-trait Node: Any {
-    fn write_to_buf(&self, buf: &mut dyn Buf);
-
-    /// return the possible count of inner bytes within this node
-    fn len(&self) -> i32;
-}
-
-pub type InnerByte = u8;
-pub type InnerBuffer = Vec<InnerByte>;
-
-pub trait Buf {
-    fn push_vec(&mut self, s: &InnerBuffer);
-    fn push_str(&mut self, s: &str);
-    fn push_char(&mut self, ch: char);
-    fn push(&mut self, ch: InnerByte);
-}
-
-
-struct Passage {
-    paragraphs: Vec<Box<dyn Node>>
-}
-
-
-struct PlainParagraph {
-    text: Box<dyn Node>
-}
-
-
-struct Heading {
-    rank: i32,
-    text: Box<dyn Node>,
-}
-
-struct OrderedList {
-    list: Vec<Box<dyn Node>>
-}
-
-struct UnorderedList {
-    list: Vec<Box<dyn Node>>
-}
-
-struct Quote {
-    list: Vec<Box<dyn Node>>,
-}
-
-struct CodeBlock {
-    language: InnerBuffer,
-    text: InnerBuffer,
-}
-
-struct Text {
-    nodes: Vec<Box<dyn Node>>
-}
-
-struct Bold {
-    text: Box<dyn Node>
-}
-
-struct MathDisplay {
-    formula: InnerBuffer
-}
-
-struct Italic {
-    text: Box<dyn Node>
-}
-
-struct Deleted {
-    text: Box<dyn Node>
-}
-
-struct CodeInline {
-    code: InnerBuffer
-}
-
-struct MathInline {
-    formula: InnerBuffer
-}
-
-struct Image {
-    alt: InnerBuffer,
-    url: InnerBuffer,
-    title: InnerBuffer,
-}
-
-struct Url {
-    text: InnerBuffer,
-    url: InnerBuffer,
-    title: InnerBuffer,
-}
+//! Markdown parsing after the CommonMark spec and its GFM extensions.
+//!
+//! Parsing runs in the two phases of the reference implementation: [`block`]
+//! feeds the input line by line into a tree of container and leaf blocks,
+//! collecting link reference definitions on the way, and [`inline`] then
+//! parses the text of every paragraph, heading and table cell.
+
+mod block;
+mod inline;
+
+use crate::ast::*;
+use crate::options::Options;
+use crate::position::LineIndex;
+
+pub(crate) use self::inline::decode_references;
+
+/// How deeply blocks and inline elements may nest; anything deeper is left
+/// as text so that walking the tree cannot overflow the stack.
+const MAX_NESTING: usize = 100;
+
+/// Text gathered from one or more source lines, remembering where every
+/// piece came from so inline nodes can be given spans.
+#[derive(Debug, Clone, Default)]
+struct Content {
+    text: String,
+    /// `(text offset, source offset)` at the start of every piece
+    map: Vec<(usize, usize)>,
+}
+
+impl Content {
+    fn push(&mut self, s: &str, source: usize) {
+        self.map.push((self.text.len(), source));
+        self.text.push_str(s);
+    }
+
+    /// The source offset of text offset `offset`.
+    fn source(&self, offset: usize) -> usize {
+        let i = match self.map.binary_search_by(|x| x.0.cmp(&offset)) {
+            Ok(i) => i,
+            Err(0) => return offset,
+            Err(i) => i - 1,
+        };
+        self.map[i].1 + (offset - self.map[i].0)
+    }
 
-impl Node for Passage {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        self.paragraphs.iter().for_each(|x| x.write_to_buf(buf));
+    /// The located span of text range `start..end`.
+    fn span(&self, start: usize, end: usize, index: &LineIndex) -> Span {
+        let source_start = self.source(start);
+        let source_end = if end > start { self.source(end - 1) + 1 } else { source_start };
+        Span { start: index.position(source_start), end: index.position(source_end) }
     }
 
-    fn len(&self) -> i32 {
-        let mut x = 0;
-        for y in &self.paragraphs {
-            x += y.len();
+    /// Drops the first `n` bytes.
+    fn advance(&mut self, n: usize) {
+        let start = self.source(n);
+        self.text.drain(..n);
+        self.map.retain(|x| x.0 > n);
+        for x in &mut self.map {
+            x.0 -= n;
         }
-        return x;
+        self.map.insert(0, (0, start));
     }
 }
 
-impl Node for PlainParagraph {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<p>");
-        self.text.write_to_buf(buf);
-        buf.push_str("</p>");
-    }
-
-    fn len(&self) -> i32 {
-        self.text.len()
-    }
+pub(crate) fn parse(input: &str, options: &Options) -> Document {
+    let index = LineIndex::new(input.as_bytes());
+    let tree = block::parse(input, options);
+    tree.document(input, options, &index)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl Node for Heading {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str(&format!("<h{}>", self.rank));
-        self.text.write_to_buf(buf);
-        buf.push_str(&format!("</h{}>", self.rank));
-    }
-
-    fn len(&self) -> i32 {
-        self.text.len()
+    fn easy_parse(s: &str, options: &Options) -> String {
+        crate::to_html_with_options(s, options).unwrap()
     }
-}
 
-
-impl Node for OrderedList {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<ol>");
-        for x in &self.list {
-            buf.push_str("<li>");
-            x.write_to_buf(buf);
-            buf.push_str("</li>");
+    /// Drops the line breaks the spec puts around tags, which is how the
+    /// expected output of `gfm_tests.in` is written.
+    fn normalize(html: &str) -> String {
+        let mut out = String::new();
+        let mut previous = None;
+        let mut chars = html.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !(c == '\n' && (previous == Some('>') || chars.peek() == Some(&'<'))) {
+                out.push(c);
+            }
+            previous = Some(c);
         }
-        buf.push_str("</ol>");
+        out
     }
 
-    fn len(&self) -> i32 {
-        let mut x = 0;
-        self.list.iter().for_each(|y| x += y.len());
-        return x;
-    }
-}
-
-
-impl Node for UnorderedList {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<ul>");
-        for x in &self.list {
-            buf.push_str("<li>");
-            x.write_to_buf(buf);
-            buf.push_str("</li>");
+    fn check(raw: &str, fnl: &str, options: &Options) {
+        let rlt = normalize(&easy_parse(raw, options));
+        if rlt != fnl {
+            panic!("Test failed. \nInput:\n{}\nResult:\n{}\nShould be:\n{}\n",
+                   raw, rlt, fnl
+            )
         }
-        buf.push_str("</ul>");
-    }
-
-    fn len(&self) -> i32 {
-        let mut x = 0;
-        self.list.iter().for_each(|y| x += y.len());
-        return x;
     }
-}
-
 
-impl Node for Quote {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<blockquote>");
-        for x in &self.list {
-            buf.push_str("<p>");
-            x.write_to_buf(buf);
-            buf.push_str("<p>");
-        }
-        buf.push_str("</blockquote>");
+    /// A CommonMark example, checked without any extension.
+    fn test(raw: &str, fnl: &str) {
+        check(raw, fnl, &Options::commonmark())
     }
 
-    fn len(&self) -> i32 {
-        let mut x = 0;
-        self.list.iter().for_each(|y| x += y.len());
-        return x;
+    /// An example of one of the GFM extensions.
+    fn test_gfm(raw: &str, fnl: &str) {
+        check(raw, fnl, &Options::default())
     }
-}
 
+    #[test]
+    fn start_test(){}
 
-impl Node for CodeBlock {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<pre class=\"lang");
-        if self.language.len() > 0 {
-            buf.push_str("-");
-            buf.push_vec(&self.language);
-            buf.push_str("\"");
+    #[test]
+    fn parses_into_typed_ast() {
+        let doc = crate::parse("## Hi *there*\n- [a](b)\n").unwrap();
+        match &doc.blocks[..] {
+            [Block::Heading(h), Block::UnorderedList(l)] => {
+                assert_eq!(h.rank, 2);
+                match &h.text.nodes[0] {
+                    Inline::Plain(p) => assert_eq!(p.text, "Hi "),
+                    x => panic!("unexpected {:?}", x),
+                }
+                match &h.text.nodes[1] {
+                    Inline::Italic(i) => assert_eq!(i.text.nodes.len(), 1),
+                    x => panic!("unexpected {:?}", x),
+                }
+                assert!(l.tight);
+                match &l.items[0].blocks[..] {
+                    [Block::PlainParagraph(p)] => match &p.text.nodes[0] {
+                        Inline::Url(u) => {
+                            assert_eq!(u.text.nodes, vec![Inline::Plain(Plain {
+                                text: "a".to_string(),
+                                span: u.text.nodes[0].span(),
+                            })]);
+                            assert_eq!(u.url, "b");
+                        }
+                        x => panic!("unexpected {:?}", x),
+                    },
+                    x => panic!("unexpected {:?}", x),
+                }
+            }
+            x => panic!("unexpected {:?}", x),
         }
-        buf.push_str(">");
-        buf.push_vec(&self.text);
-        buf.push_str("</pre>");
     }
 
-    fn len(&self) -> i32 {
-        (self.language.len() + self.text.len()) as i32
-    }
-}
-
-
-impl Node for MathDisplay {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<pre class=\"lang-math-display\">");
-        buf.push_vec(&self.formula);
-        buf.push_str("</pre>");
-    }
-
-    fn len(&self) -> i32 {
-        self.formula.len() as i32
+    #[test]
+    fn heading_styles_and_thematic_breaks() {
+        let doc = crate::parse("Foo\n===\n\n## Bar\n\n* * *\nBaz\n---\n- - -\n_____\n").unwrap();
+        let kinds: Vec<String> = doc.blocks.iter().map(|x| match x {
+            Block::Heading(h) => format!("h{} {:?}", h.rank, h.style),
+            Block::ThematicBreak(t) => format!("hr {}", t.marker),
+            x => panic!("unexpected {:?}", x),
+        }).collect();
+        assert_eq!(kinds, ["h1 Setext", "h2 Atx", "hr *", "h2 Setext", "hr -", "hr _"]);
+
+        // a `---` that can't underline a paragraph is a break, and a break
+        // wins over a list item
+        test("- foo\n---\n", "<ul><li>foo</li></ul><hr />");
+        test("* a\n* * *\n", "<ul><li>a</li></ul><hr />");
+        test("> foo\n---\n", "<blockquote><p>foo</p></blockquote><hr />");
+        test("Foo\n---\nbar\n", "<h2>Foo</h2><p>bar</p>");
     }
-}
 
-impl Node for Text {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        for x in &self.nodes {
-            x.write_to_buf(buf);
+    #[test]
+    fn indented_and_fenced_code() {
+        let doc = crate::parse("    a\n\tb\n\n```\nc\n```\n").unwrap();
+        match &doc.blocks[..] {
+            [Block::CodeBlock(a), Block::CodeBlock(b)] => {
+                assert_eq!((a.kind, a.text.as_str()), (CodeBlockKind::Indented, "a\nb\n"));
+                assert_eq!((b.kind, b.text.as_str()), (CodeBlockKind::Fenced, "c\n"));
+            }
+            x => panic!("unexpected {:?}", x),
         }
-    }
-
-    fn len(&self) -> i32 {
-        let mut y = 0;
-        self.nodes.iter().for_each(|x| y += x.len());
-        return y;
-    }
-}
-
-impl Node for Bold {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<b>");
-        self.text.write_to_buf(buf);
-        buf.push_str("</b>");
-    }
-
-    fn len(&self) -> i32 {
-        self.text.len()
-    }
-}
-
-
-impl Node for Italic {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<i>");
-        self.text.write_to_buf(buf);
-        buf.push_str("</i>");
-    }
-
-    fn len(&self) -> i32 {
-        self.text.len()
-    }
-}
-
-
-impl Node for Deleted {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<del>");
-        self.text.write_to_buf(buf);
-        buf.push_str("</del>");
-    }
-
-    fn len(&self) -> i32 {
-        self.text.len()
-    }
-}
-
-
-impl Node for CodeInline {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<pre><code>");
-        buf.push_vec(&self.code);
-        buf.push_str("</code><pre>");
-    }
-
-    fn len(&self) -> i32 {
-        self.code.len() as i32
-    }
-}
-
-
-impl Node for MathInline {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<div class=\"lang-math-inline\">");
-        buf.push_vec(&self.formula);
-        buf.push_str("</div>");
-    }
 
-    fn len(&self) -> i32 {
-        self.formula.len() as i32
-    }
-}
-
-impl Node for Image {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<img src=\"");
-        buf.push_vec(&self.url);
-        buf.push_str("\" alt=\"");
-        buf.push_vec(&self.alt);
-        buf.push_str("\" title=\"");
-        buf.push_vec(&self.title);
-        buf.push_str("\">");
+        // tabs expand to the next multiple of four columns, and only the
+        // columns the block needs are taken from a tab
+        test(" \tfoo\n", "<pre><code>foo</code></pre>");
+        test("  \tfoo\tbar\n", "<pre><code>foo\tbar</code></pre>");
+        test(">\t\tfoo\n", "<blockquote><pre><code>  foo</code></pre></blockquote>");
+        test("- a\n\n\t  b\n", "<ul><li><p>a</p><pre><code>b</code></pre></li></ul>");
+        // an indented line can't interrupt a paragraph
+        test("a\n    b\n", "<p>a\nb</p>");
+        // trailing blank lines are not part of the code
+        test("    a\n\n\n", "<pre><code>a</code></pre>");
     }
 
-    fn len(&self) -> i32 {
-        (self.alt.len() + self.url.len() + self.title.len()) as i32
+    #[test]
+    fn fenced_code_info_strings() {
+        let info = |s: &str| match &crate::parse(&format!("```{}\n```\n", s)).unwrap().blocks[..] {
+            [Block::CodeBlock(c)] => (c.language.clone(), c.attributes.clone(), c.highlights.clone()),
+            x => panic!("unexpected {:?}", x),
+        };
+        let attribute = |k: &str, v: &str| (k.to_string(), v.to_string());
+        assert_eq!(info("c++"), ("c++".to_string(), vec![], vec![]));
+        assert_eq!(info(" objective-c  "), ("objective-c".to_string(), vec![], vec![]));
+        assert_eq!(info("rust title=\"main.rs\" {1,3-5} no_run lines=2"), (
+            "rust".to_string(),
+            vec![attribute("title", "main.rs"), attribute("no_run", ""), attribute("lines", "2")],
+            vec![(1, 1), (3, 5)],
+        ));
+        assert_eq!(info("js{2, 9-7, x,4-4}"), ("js".to_string(), vec![], vec![(2, 2), (4, 4)]));
+        assert_eq!(info("{1}"), (String::new(), vec![], vec![(1, 1)]));
+        assert_eq!(info("py title=\"a b"), ("py".to_string(), vec![attribute("title", "a b")], vec![]));
+
+        test("~~~ c++ {1}\n```\n~~~\n", "<pre><code class=\"language-c++\">```</code></pre>");
+        // the closing fence is at least as long as the opening one, of the
+        // same character, and indented by less than four columns
+        test("````\na\n```\n    ````\n``````\nb\n", "<pre><code>a\n```\n    ````</code></pre><p>b</p>");
+        // the indentation of the opening fence is stripped from every line
+        test("  ```\n a\n  b\n   c\n  ```\n", "<pre><code>a\nb\n c</code></pre>");
+        // an unclosed fence runs to the end of its container
+        test("> ```\n> a\n\nb\n", "<blockquote><pre><code>a</code></pre></blockquote><p>b</p>");
+        test("```\na\n", "<pre><code>a</code></pre>");
     }
-}
 
-impl Node for Url {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push_str("<a href=\"");
-        buf.push_vec(&self.url);
-        buf.push_str("\" title=\"");
-        buf.push_vec(&self.title);
-        buf.push_str("\">");
-        buf.push_vec(&self.text);
-        buf.push_str("</a>");
+    #[test]
+    fn nested_and_loose_lists() {
+        let doc = crate::parse("1. a\n\n   b\n2. c\n").unwrap();
+        match &doc.blocks[..] {
+            [Block::OrderedList(l)] => {
+                assert!(!l.tight);
+                assert_eq!(l.items.len(), 2);
+                assert_eq!(l.items[0].blocks.len(), 2);
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // items nest by the indentation of their content, not of the marker
+        test("- a\n  - b\n    - c\n", "<ul><li>a<ul><li>b<ul><li>c</li></ul></li></ul></li></ul>");
+        test("- a\n - b\n", "<ul><li>a</li><li>b</li></ul>");
+        test("10. a\n    - b\n", "<ol start=\"10\"><li>a<ul><li>b</li></ul></li></ol>");
+        // items hold any block, and paragraphs continue lazily
+        test("- > a\nb\n", "<ul><li><blockquote><p>a\nb</p></blockquote></li></ul>");
+        test("- a\n\n      code\n", "<ul><li><p>a</p><pre><code>code</code></pre></li></ul>");
+        // a blank line between items or blocks makes the list loose, one
+        // inside a nested list only loosens that list
+        test("- a\n\n- b\n", "<ul><li><p>a</p></li><li><p>b</p></li></ul>");
+        test("- a\n  - b\n\n  - c\n", "<ul><li>a<ul><li><p>b</p></li><li><p>c</p></li></ul></li></ul>");
+        // changing the bullet or delimiter starts a new list
+        test("- a\n+ b\n1. c\n1) d\n",
+             "<ul><li>a</li></ul><ul><li>b</li></ul><ol><li>c</li></ol><ol><li>d</li></ol>");
+        // only a list starting at 1 can interrupt a paragraph
+        test("a\n2. b\n", "<p>a\n2. b</p>");
     }
 
-    fn len(&self) -> i32 {
-        (self.text.len() + self.url.len() + self.title.len()) as i32
-    }
-}
-
-impl Node for InnerByte {
-    fn write_to_buf(&self, buf: &mut dyn Buf) {
-        buf.push(*self);
+    #[test]
+    fn list_markers() {
+        let doc = crate::parse("3) a\n4) b\n5. c\n\n* d\n").unwrap();
+        let markers: Vec<String> = doc.blocks.iter().map(|x| match x {
+            Block::OrderedList(l) => format!("{}{} {}", l.start, l.delimiter, l.items.len()),
+            Block::UnorderedList(l) => format!("{} {}", l.bullet, l.items.len()),
+            x => panic!("unexpected {:?}", x),
+        }).collect();
+        assert_eq!(markers, ["3) 2", "5. 1", "* 1"]);
+
+        test("3. a\n", "<ol start=\"3\"><li>a</li></ol>");
+        test("0) a\n", "<ol start=\"0\"><li>a</li></ol>");
+        test("1234567890. a\n", "<p>1234567890. a</p>");
+
+        // `=` is only a bullet when asked for, and can still underline a heading
+        test("= a\n", "<p>= a</p>");
+        let options = Options { equals_bullet: true, ..Options::commonmark() };
+        check("= a\n= b\n- c\n", "<ul><li>a</li><li>b</li></ul><ul><li>c</li></ul>", &options);
+        check("a\n=\n", "<h1>a</h1>", &options);
     }
 
-    fn len(&self) -> i32 {
-        1
+    #[test]
+    fn nested_blocks_in_quotes() {
+        let doc = crate::parse("> # a\n> > b\n").unwrap();
+        match &doc.blocks[..] {
+            [Block::Quote(q)] => match &q.blocks[..] {
+                [Block::Heading(_), Block::Quote(inner)] => assert_eq!(inner.blocks.len(), 1),
+                x => panic!("unexpected {:?}", x),
+            },
+            x => panic!("unexpected {:?}", x),
+        }
+
+        test(">> a\n", "<blockquote><blockquote><p>a</p></blockquote></blockquote>");
+        test("> - a\n> - b\n", "<blockquote><ul><li>a</li><li>b</li></ul></blockquote>");
+        test(">     code\n", "<blockquote><pre><code>code</code></pre></blockquote>");
+        test("> ```\n> a\n\nb\n", "<blockquote><pre><code>a</code></pre></blockquote><p>b</p>");
+        test_gfm("> $$\n> x\n> $$\n", "<blockquote><pre class=\"lang-math-display\">x</pre></blockquote>");
+        // paragraphs continue lazily, other blocks don't
+        test("> a\nb\n", "<blockquote><p>a\nb</p></blockquote>");
+        test("> - a\n- b\n", "<blockquote><ul><li>a</li></ul></blockquote><ul><li>b</li></ul>");
+        test("> a\n---\n", "<blockquote><p>a</p></blockquote><hr />");
+        // a blank line ends the quote
+        test("> a\n\n> b\n", "<blockquote><p>a</p></blockquote><blockquote><p>b</p></blockquote>");
+        test(">\n", "<blockquote></blockquote>");
     }
-}
 
-fn encase<T: Node + 'static>(x: Option<(T, usize)>) -> Option<(Box<dyn Node>, usize)> {
-    x.map(|(y, z)| (Box::new(y) as _, z))
-}
-
-mod buf_util {
-    use super::InnerBuffer;
-
-    fn trim_left(buf: &mut InnerBuffer) {
-        let mut j = 0;
-        let mut first = true;
-        for i in 0..buf.len() {
-            if !first || !buf[i].is_ascii_whitespace() {
-                buf[j] = buf[i];
-                j += 1;
-                first = false;
+    #[test]
+    fn pipe_tables() {
+        let doc = crate::parse("| a | b | c | d |\n|---|:--|:-:|--:|\n| 1 |\n").unwrap();
+        match &doc.blocks[..] {
+            [Block::Table(t)] => {
+                assert_eq!(t.alignments,
+                           [Alignment::None, Alignment::Left, Alignment::Center, Alignment::Right]);
+                assert_eq!(t.header.cells.len(), 4);
+                // short rows are padded to the width of the header
+                assert_eq!(t.rows[0].cells.len(), 4);
             }
-        }
-        for i in j..buf.len() {
-            buf.pop();
-        }
-    }
-
-    fn trim_right(buf: &mut InnerBuffer) {
-        for i in (0..buf.len()).rev() {
-            if buf[i].is_ascii_whitespace() {
-                buf.pop();
-            } else {
-                break;
+            x => panic!("unexpected {:?}", x),
+        }
+
+        test_gfm("a | b\n--|--\n*x* | `y\\|z`\n",
+                 "<table><thead><tr><th>a</th><th>b</th></tr></thead>\
+                  <tbody><tr><td><em>x</em></td><td><code>y|z</code></td></tr></tbody></table>");
+        // cell spans cover the escaped pipes
+        let input = "| a | `b\\|c` |\n| - | - |\n";
+        match &crate::parse(input).unwrap().blocks[..] {
+            [Block::Table(t)] => {
+                let spans: Vec<_> = t.header.cells.iter().map(|x| &input[x.span.start.offset..x.span.end.offset]).collect();
+                assert_eq!(spans, ["a", "`b\\|c`"]);
             }
+            x => panic!("unexpected {:?}", x),
+        }
+        // extra cells are dropped
+        test_gfm("| a |\n| - |\n| b | c |\n",
+                 "<table><thead><tr><th>a</th></tr></thead><tbody><tr><td>b</td></tr></tbody></table>");
+        // the delimiter row must match the header
+        test_gfm("| a | b |\n| - |\n", "<p>| a | b |\n| - |</p>");
+        // a table interrupts a paragraph, and ends at a blank line or another block
+        test_gfm("p\n| a |\n| - |\n> q\n",
+                 "<p>p</p><table><thead><tr><th>a</th></tr></thead></table><blockquote><p>q</p></blockquote>");
+        // tables are an extension
+        test("| a |\n| - |\n", "<p>| a |\n| - |</p>");
+
+        // a paragraph of definitions only has nothing to underline, so the
+        // line after it starts a block of its own
+        for options in &[Options::default(), Options::commonmark()] {
+            check("[foo]: /url\n---\n", "<hr />", options);
+            check("[a]: /u\n-\n", "<ul><li></li></ul>", options);
+            check("> [a]: /u\n> -\n", "<blockquote><ul><li></li></ul></blockquote>", options);
+            check("- [a]: /u\n  -\n", "<ul><li><ul><li></li></ul></li></ul>", options);
+            check("[a]: /u\n===\n[a]\n", "<p>===<a href=\"/u\">a</a></p>", options);
         }
     }
 
-    fn trim(buf: &mut InnerBuffer) {
-        trim_right(buf);
-        trim_left(buf);
-    }
-
-    pub(crate) fn is(text: &InnerBuffer, pos: usize, s: &str) -> bool {
-        if s == "" { return false; }
-        if pos + s.len() > text.len() {
-            return false;
-        }
-        let xx = s.as_bytes();
-        for i in 0..xx.len() {
-            if text[pos + i] != xx[i] {
-                return false;
-            }
+    #[test]
+    fn table_padding_is_bounded() {
+        // every short row under a wide header is padded to its width, so
+        // past a total of half a million padded cells lines stop being rows
+        let input = format!("{}|\n{}|\n{}", "| a ".repeat(1000), "|-".repeat(1000), "| x\n".repeat(1000));
+        let doc = crate::parse(&input).unwrap();
+        match &doc.blocks[..] {
+            [Block::Table(t), Block::PlainParagraph(_)] => assert_eq!(t.rows.len(), 524),
+            x => panic!("unexpected {:?}", x),
         }
-        return true;
+        let html = crate::to_html(&format!("{}|\n{}|\n{}", "|a".repeat(2000), "|-".repeat(2000), "|\n".repeat(5000))).unwrap();
+        assert!(html.len() < 8 << 20, "{} bytes", html.len());
     }
 
-    pub(crate) fn get_word(text: &InnerBuffer, pos: usize) -> Option<(InnerBuffer, usize)> {
-        if !text[pos].is_ascii_alphanumeric() {
-            return None;
-        }
-        let mut num: InnerBuffer = vec![];
-        let mut pos = pos;
-        while pos < text.len() {
-            if !text[pos].is_ascii_alphanumeric() {
-                break;
+    #[test]
+    fn records_spans() {
+        let doc = crate::parse("# a\n\nx **yz**\n").unwrap();
+        assert_eq!(doc.blocks[0].span(), Span {
+            start: Position { offset: 0, line: 1, column: 1 },
+            end: Position { offset: 3, line: 1, column: 4 },
+        });
+        match &doc.blocks[1] {
+            Block::PlainParagraph(p) => {
+                let bold = p.text.nodes[1].span();
+                assert_eq!((bold.start.offset, bold.end.offset), (7, 13));
+                assert_eq!((bold.start.line, bold.start.column), (3, 3));
+                assert_eq!((bold.end.line, bold.end.column), (3, 9));
             }
-            num.push(text[pos]);
-            pos += 1;
+            x => panic!("unexpected {:?}", x),
         }
-        return Some((num, pos));
     }
 
-    pub(crate) fn get_until_pattern(text: &InnerBuffer, pos: usize, until: impl Fn(usize) -> Option<usize>, new_line: bool) -> GetUntilResult {
-        let mut buf = InnerBuffer::new();
-        let mut pos = pos;
-        while pos < text.len() {
-            if !new_line && is(&text, pos, "\n") {
-                pos = check_eat(&text, pos, "\n", 1);
-                return GetUntilResult {
-                    text: buf,
-                    pos,
-                    reached_target: false,
-//                    die_of_newline: true,
-//                    die_of_eof: false,
-                };
-            }
-            match until(pos) {
-                Some(p) => {
-                    pos = p;
-                    return GetUntilResult {
-                        text: buf,
-
-                        pos,
-                        reached_target: true,
-//                        die_of_newline: false,
-//                        die_of_eof: false,
-                    };
-                }
-                None => {}
-            }
-            buf.push(text[pos]);
-            pos += 1;
+    #[test]
+    fn records_spans_inside_containers() {
+        let doc = crate::parse("> - a\n>   *b*\n").unwrap();
+        let quote = doc.blocks[0].span();
+        assert_eq!((quote.start.offset, quote.end.offset), (0, 13));
+        match &doc.blocks[0] {
+            Block::Quote(q) => match &q.blocks[0] {
+                Block::UnorderedList(l) => match &l.items[0].blocks[0] {
+                    Block::PlainParagraph(p) => {
+                        let italic = p.text.nodes[2].span();
+                        assert_eq!((italic.start.line, italic.start.column), (2, 5));
+                        assert_eq!((italic.end.line, italic.end.column), (2, 8));
+                    }
+                    x => panic!("unexpected {:?}", x),
+                },
+                x => panic!("unexpected {:?}", x),
+            },
+            x => panic!("unexpected {:?}", x),
         }
-        return GetUntilResult {
-            text: buf,
-            pos,
-            reached_target: false,
-//            die_of_newline: false,
-//            die_of_eof: true,
-        };
     }
 
-    pub(crate) fn get_until(text: &InnerBuffer, pos: usize, until: &str, multi_lines: bool) -> GetUntilResult {
-        let judge = |pz: usize| {
-            if is(&text, pz, until) {
-                Some(check_eat(&text, pz, until, 1))
-            } else {
-                None
-            }
-        };
-        get_until_pattern(&text, pos, judge, multi_lines)
+    #[test]
+    fn rejects_invalid_utf8() {
+        let err = crate::parse_bytes_with_options(b"# a\nb \xff c\n", &Options::default()).unwrap_err();
+        assert_eq!(err, crate::ParseError::InvalidUtf8 { at: Position { offset: 6, line: 2, column: 3 } });
+        assert_eq!(err.to_string(), "invalid UTF-8 at line 2, column 3");
+        assert!(crate::parse_bytes_with_options("ä".as_bytes(), &Options::default()).is_ok());
     }
 
-    use super::*;
-    pub(crate) fn character(text: &InnerBuffer, pos: usize) -> InnerByte {
-        if pos < text.len() {
-            let ch = text[pos];
-
-            // REPLACEMENT CHARACTER according to GFM
-            // if InnerByte less than 2 bytes, maybe this will not work
-            if ch == 0 as InnerByte {
-                if TypeId::of::<InnerByte>() == TypeId::of::<char>() ||
-                    TypeId::of::<InnerByte>() == TypeId::of::<i32>() ||
-                    TypeId::of::<InnerByte>() == TypeId::of::<u32>() ||
-                    TypeId::of::<InnerByte>() == TypeId::of::<i16>() ||
-                    TypeId::of::<InnerByte>() == TypeId::of::<u16>()
-                {
-                    return '\u{FFFD}' as InnerByte;
-                }
-            }
-            return ch;
-        }
-        return (-1i32) as InnerByte; // EOF
-    }
+    #[test]
+    fn safe_mode_drops_script_urls() {
+        let options = Options { safe_mode: Some(crate::SafeMode::default()), ..Options::default() };
+        let html = crate::to_html_with_options("[a](javascript:alert(1)) ![b](data:x) [c](/d)\n", &options).unwrap();
+        assert_eq!(html, "<p><a href=\"\">a</a> <img src=\"\" alt=\"b\" /> <a href=\"/d\">c</a></p>\n");
 
-    pub(crate) fn is_eof(text: &InnerBuffer, pos: usize) -> bool {
-        character(&text, pos) == (-1i32) as InnerByte
+        let doc = crate::parse_with_options("[a](javascript:x)\n", &options).unwrap();
+        let mut buf: Vec<u8> = vec![];
+        doc.write_to_buf(&mut buf, &options);
+        assert_eq!(buf, b"<p><a href=\"\">a</a></p>\n");
     }
 
-    /// returns 1 if a newline (U+000A)
-    /// returns 1 if a carriage return (U+000D) not followed by a newline
-    /// or returns 2 if a carriage return and a following newline.
-    fn is_line_ending(text: &InnerBuffer, pos: usize) -> i32 {
-        if character(&text, pos) == '\n' as InnerByte {
-            return 1;
-        }
-        if character(&text, pos) == '\r' as InnerByte {
-            if character(&text, pos + 1) == '\n' as InnerByte {
-                return 2;
-            }
-            return 1;
-        }
-        return 0;
+    #[test]
+    fn safe_mode_sanitizes_raw_html() {
+        let options = Options { safe_mode: Some(crate::SafeMode::default()), ..Options::default() };
+        let html = crate::to_html_with_options("<script>x</script>\n\na <b onclick=\"y\">c</b>\n", &options).unwrap();
+        assert_eq!(html, "&lt;script>x&lt;/script>\n<p>a &lt;b onclick=\"y\">c&lt;/b></p>\n");
     }
 
-    pub(crate) fn check_eat(text: &InnerBuffer, pos: usize, s: &str, cnt: i32) -> usize {
-        if s != "" {
-            if count(text, pos, s) < cnt {
-                panic!("Cannot eat {} for {} time(s)", s, cnt);
+    #[test]
+    fn raw_html() {
+        let doc = crate::parse("<div>\n*a*\n</div>\n\nb <!-- c --> <?d?>\n").unwrap();
+        match &doc.blocks[..] {
+            [Block::HtmlBlock(h), Block::PlainParagraph(p)] => {
+                assert_eq!(h.html, "<div>\n*a*\n</div>");
+                let html: Vec<_> = p.text.nodes.iter().filter_map(|x| match x {
+                    Inline::HtmlInline(h) => Some(h.html.as_str()),
+                    _ => None,
+                }).collect();
+                assert_eq!(html, ["<!-- c -->", "<?d?>"]);
             }
-            return pos + s.len() * cnt as usize;
-        } else {
-            return pos + cnt as usize;
+            x => panic!("unexpected {:?}", x),
         }
-    }
 
-    pub(crate) fn count(text: &InnerBuffer, pos: usize, s: &str) -> i32 {
-        let mut cnt = 0;
-        let mut p = pos;
-        while p + s.len() <= text.len() {
-            if is(&text, p, s) {
-                p += s.len();
-                cnt += 1;
-            } else { break; }
-        }
-        return cnt;
+        let input = "<div>\n<b>x</b>\n</div>\n\na <i>b</i>\n";
+        test(input, "<div><b>x</b></div><p>a <i>b</i></p>");
+        let escape = Options { raw_html: crate::RawHtml::Escape, ..Options::commonmark() };
+        check(input, "<p>&lt;div&gt;\n&lt;b&gt;x&lt;/b&gt;\n&lt;/div&gt;</p><p>a &lt;i&gt;b&lt;/i&gt;</p>", &escape);
+        let drop = Options { raw_html: crate::RawHtml::Drop, ..Options::commonmark() };
+        check(input, "<p>a b</p>", &drop);
+        // escaping wins over the tag filter and safe mode
+        let escape = Options { raw_html: crate::RawHtml::Escape, safe_mode: Some(crate::SafeMode::default()), ..Options::default() };
+        check("<script>\n", "<p>&lt;script&gt;</p>", &escape);
     }
 
-    pub struct GetUntilResult {
-        pub(crate) text: InnerBuffer,
-        pub(crate) pos: usize,
-        pub(crate) reached_target: bool,
-//    die_of_newline: bool,
-//    die_of_eof: bool,
-    }
-}
-
-
-pub fn parse(input: &InnerBuffer, buf: &mut dyn Buf) {
-    match passage(input, 0) {
-        Some((p, _)) => {
-//                Parser::readjust(&mut p);
-            p.write_to_buf(buf);
-        }
-        None => panic!("unexpected error: unknown reason")
+    #[test]
+    fn reference_links() {
+        let defs = "\n\n[rust]: https://www.rust-lang.org \"Rust\"\n[The  Book]: <book.html>\n";
+        let rust = "<a href=\"https://www.rust-lang.org\" title=\"Rust\">";
+        // full, collapsed and shortcut references
+        test(&format!("[this][Rust] [Rust][] [Rust]{}", defs),
+             &format!("<p>{0}this</a> {0}Rust</a> {0}Rust</a></p>", rust));
+        // labels match case-insensitively, with Unicode case folding and
+        // collapsed whitespace
+        test(&format!("[the\nbook]{}", defs), "<p><a href=\"book.html\">the\nbook</a></p>");
+        test("[ẞ]\n\n[SS]: /s\n", "<p><a href=\"/s\">ẞ</a></p>");
+        // the first definition wins, and definitions never show up
+        test("[a]\n\n[a]: /1\n[A]: /2\n", "<p><a href=\"/1\">a</a></p>");
+        test("[a]: /x\n", "");
+        // an undefined label is text, and links can't contain links
+        test("[b][a]\n", "<p>[b][a]</p>");
+        test("[b [c](/y)][a]\n\n[a]: /x\n", "<p>[b <a href=\"/y\">c</a>]<a href=\"/x\">a</a></p>");
+        // a definition can't interrupt a paragraph
+        test("p\n[a]: /x\n", "<p>p\n[a]: /x</p>");
     }
-}
 
-//    fn readjust(node: &mut dyn Node) {
-//        fn is<T0: ?Sized + Any, T: ?Sized + Any>(_s: &T) -> bool {
-//            TypeId::of::<T0>() == TypeId::of::<T>()
-//        }
-//
-//        if is::<OrderedList, _>(node) {
-//            println!("Node is ordered list");
-//        }
-//    }
-fn passage(text: &InnerBuffer, pos: usize) -> Option<(Passage, usize)> {
-    let mut pos = pos;
-    let mut psg = Passage { paragraphs: vec![] };
-    while pos < text.len() {
-        match paragraph(&text, pos) {
-            Some((x, p)) => {
-//                    println!("Matched {} before {}", x.get_node_type(), p);
-                psg.paragraphs.push(x);
-                if pos == p {
-                    panic!("Unexpected error: same location: {}", p);
-                }
-                pos = p;
-            }
-            None => panic!("Unexpected error: unknown reason")
-        }
+    #[test]
+    fn emphasis_delimiter_runs() {
+        test("_a_ __b__ *c* **d**\n", "<p><em>a</em> <strong>b</strong> <em>c</em> <strong>d</strong></p>");
+        test("***a*** ___b___ **_c_**\n",
+             "<p><em><strong>a</strong></em> <em><strong>b</strong></em> <strong><em>c</em></strong></p>");
+        // `_` can't open or close inside a word, `*` can
+        test("snake_case_name a*b*c\n", "<p>snake_case_name a<em>b</em>c</p>");
+        // a run must be left-flanking to open and right-flanking to close
+        test("a * b * c\n", "<p>a * b * c</p>");
+        test("*a *b*\n", "<p>*a <em>b</em></p>");
+        test("*(*a*)*\n", "<p><em>(<em>a</em>)</em></p>");
+        // the rule of three: a run that can both open and close can't match
+        // one whose combined length is a multiple of three
+        test("*a**b*\n", "<p><em>a**b</em></p>");
+        test("*a**b**c*\n", "<p><em>a<strong>b</strong>c</em></p>");
+        // unmatched delimiters stay text, and emphasis can't span a link
+        test("**a*\n", "<p>*<em>a</em></p>");
+        test("*[a*](b)\n", "<p>*<a href=\"b\">a*</a></p>");
+
+        // strikethrough shares the stack, with runs of one or two tildes that
+        // only close runs of the same length
+        test_gfm("~a~ ~~b~~ ~~~c~~~ ~d~~\n", "<p><del>a</del> <del>b</del> ~~~c~~~ ~d~~</p>");
+        test_gfm("*~~a~~* ~~*b*~~\n", "<p><em><del>a</del></em> <del><em>b</em></del></p>");
+        test("~~a~~\n", "<p>~~a~~</p>");
     }
-    return Some((psg, pos));
-}
-
-fn paragraph(text: &InnerBuffer, pos: usize) -> Option<(Box<dyn Node>, usize)> {
-    let heading = heading(&text, pos);
-    if heading.is_some() { return encase(heading); }
-    let ordered_list = ordered_list(&text, pos);
-    if ordered_list.is_some() { return encase(ordered_list); }
-
-    let unordered_list = unordered_list(&text, pos);
-    if unordered_list.is_some() { return encase(unordered_list); }
-
-    let quote = quote(&text, pos);
-    if quote.is_some() { return encase(quote); }
 
-    let code_block = code_block(&text, pos);
-    if code_block.is_some() { return encase(code_block); }
-
-    let math_display = math_display(&text, pos);
-    if math_display.is_some() { return encase(math_display); }
-
-    let plain_paragraph = plain_paragraph(&text, pos);
-    if plain_paragraph.is_some() { return encase(plain_paragraph); }
+    #[test]
+    fn line_breaks() {
+        let doc = crate::parse("a  \nb\\\nc\nd\n").unwrap();
+        let kinds: Vec<&str> = match &doc.blocks[..] {
+            [Block::PlainParagraph(p)] => p.text.nodes.iter().map(|x| match x {
+                Inline::Plain(_) => "text",
+                Inline::HardBreak(_) => "hard",
+                Inline::SoftBreak(_) => "soft",
+                x => panic!("unexpected {:?}", x),
+            }).collect(),
+            x => panic!("unexpected {:?}", x),
+        };
+        assert_eq!(kinds, ["text", "hard", "text", "hard", "text", "soft", "text"]);
 
-    return None;
-}
+        test("a  \n  b\\\nc \nd\n", "<p>a<br />b<br />c\nd</p>");
+        // not at the end of a block, and not in code
+        test("a  \n\n# b\\\n", "<p>a</p><h1>b\\</h1>");
+        test("`a  \nb`\n", "<p><code>a   b</code></p>");
 
-fn plain_paragraph(text: &InnerBuffer, pos: usize) -> Option<(PlainParagraph, usize)> {
-    match inline(text, pos, false, "", true, true, true) {
-        Some((x, y)) => {
-//                if x.len() > 0 {
-            Some((PlainParagraph { text: Box::new(x) }, y))
-//                } else {
-//                    None
-//                }
-        }
-        None => None
-    }
-}
-use buf_util::*;
-fn heading(text: &InnerBuffer, pos: usize) -> Option<(Heading, usize)> {
-    let rank = count(&text, pos, "#");
-    if rank < 1 { return None; }
-    let pos = check_eat(&text, pos, "#", rank);
-    let (text, new_pos) = inline(text, pos, false, "", true, true, true).unwrap();
-    if rank > 6 {
-        return None;
+        let input = "a\nb  \nc\n";
+        let space = Options { soft_break: crate::SoftBreakStyle::Space, ..Options::commonmark() };
+        check(input, "<p>a b<br />c</p>", &space);
+        let hard = Options { soft_break: crate::SoftBreakStyle::HardBreak, ..Options::commonmark() };
+        check(input, "<p>a<br />b<br />c</p>", &hard);
     }
-    return Some((Heading { rank, text: Box::new(text) }, new_pos));
-}
 
-fn ordered_list(text: &InnerBuffer, pos: usize) -> Option<(OrderedList, usize)> {
-    let mut pos = pos;
-    let is_item_number = |pz: usize| -> Option<usize> {
-        let mut pz = pz;
-        while character(&text, pz).is_ascii_digit() {
-            pz += 1;
-        }
-        if is(&text, pz, ". ") {
-            return Some(check_eat(&text, pz, ". ", 1));
-        }
-        return None;
-    };
-    let mut list = OrderedList { list: vec![] };
-    loop {
-        match is_item_number(pos) {
-            Some(p) => pos = p,
-            None => break
-        }
-        match inline(text, pos, false, "", true, true, true) {
-            Some((x, p)) => {
-                list.list.push(Box::new(x));
-                pos = p;
-            }
-            None => break
-        }
-    }
-    if list.len() > 0 {
-        return Some((list, pos));
-    } else {
-        return None;
+    #[test]
+    fn escapes_and_entities() {
+        // every ASCII punctuation character can be escaped, and comes out
+        // escaped again where HTML needs it
+        let punctuation = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+        let escaped: String = punctuation.chars().flat_map(|c| vec!['\\', c]).collect();
+        test(&escaped, "<p>!&quot;#$%&amp;'()*+,-./:;&lt;=&gt;?@[\\]^_`{|}~</p>");
+        test_gfm("\\$a$ \\~b~ \\[^c]\n", "<p>$a$ ~b~ [^c]</p>");
+        // other characters keep their backslash, and code keeps every one
+        test("\\a \\é `\\*`\n", "<p>\\a \\é <code>\\*</code></p>");
+
+        test("&amp; &copy; &CounterClockwiseContourIntegral; &#123; &#x1F600; &#X22;\n",
+             "<p>&amp; \u{a9} \u{2233} { \u{1f600} &quot;</p>");
+        // invalid code points become U+FFFD, unknown names stay as they are
+        test("&#0; &#1234567890; &#xD800; &bogus; &copy\n", "<p>\u{fffd} &amp;#1234567890; \u{fffd} &amp;bogus; &amp;copy</p>");
+        // references are decoded in urls, titles and info strings, but not in code
+        test("[a](/&ouml;?x=&quot; \"&auml;\")\n", "<p><a href=\"/%C3%B6?x=%22\" title=\"\u{e4}\">a</a></p>");
+        test("``` f&ouml;o\n```\n", "<pre><code class=\"language-f\u{f6}o\"></code></pre>");
+        test("`&amp;`\n", "<p><code>&amp;amp;</code></p>");
+        // a decoded character has no markdown meaning
+        test("&#42;a&#42;\n", "<p>*a*</p>");
     }
-}
 
-fn unordered_list(text: &InnerBuffer, pos: usize) -> Option<(UnorderedList, usize)> {
-    let mut pos = pos;
-    let is_item_prefix = |pz: usize| -> Option<usize> {
-        let mut pz = pz;
-        let ch = character(&text, pz);
-        pz += 1;
-        for c in "-=*".as_bytes() {
-            if ch == *c as InnerByte {
-                if is(&text, pz, " ") {
-                    return Some(pz + 1);
-                }
-            }
-        }
-        return None;
-    };
-    let mut list = UnorderedList { list: vec![] };
-    loop {
-        match is_item_prefix(pos) {
-            Some(p) => pos = p,
-            None => break
-        }
-        match inline(&text, pos, false, "", true, true, true) {
-            Some((x, p)) => {
-                list.list.push(Box::new(x));
-                pos = p;
-            }
-            None => break
-        }
-    }
-    if list.len() > 0 {
-        return Some((list, pos));
-    } else {
-        return None;
+    #[test]
+    fn autolinks() {
+        test("<https://a.b/c?d>\n", "<p><a href=\"https://a.b/c?d\">https://a.b/c?d</a></p>");
+        test("<me@example.com>\n", "<p><a href=\"mailto:me@example.com\">me@example.com</a></p>");
+        // no spaces, and a scheme is needed
+        test("<https://a b>\n", "<p>&lt;https://a b&gt;</p>");
+        test("<a.b>\n", "<p>&lt;a.b&gt;</p>");
+
+        // bare urls and emails are an extension
+        test("www.a.com\n", "<p>www.a.com</p>");
+        test_gfm("see www.a.com/x and https://a.com or me@a.com\n",
+                 "<p>see <a href=\"http://www.a.com/x\">www.a.com/x</a> and \
+                  <a href=\"https://a.com\">https://a.com</a> or \
+                  <a href=\"mailto:me@a.com\">me@a.com</a></p>");
+        // trailing punctuation is left out, and so are unbalanced parentheses
+        // and what looks like an entity
+        test_gfm("(www.a.com/b).\n", "<p>(<a href=\"http://www.a.com/b\">www.a.com/b</a>).</p>");
+        test_gfm("www.a.com/(b)c)\n", "<p><a href=\"http://www.a.com/(b)c\">www.a.com/(b)c</a>)</p>");
+        test_gfm("www.a.com/b&hl;\n", "<p><a href=\"http://www.a.com/b\">www.a.com/b</a>&amp;hl;</p>");
+        test_gfm("www.a.com/b<c\n", "<p><a href=\"http://www.a.com/b\">www.a.com/b</a>&lt;c</p>");
+        // not inside links or code
+        test_gfm("[www.a.com](/x) `www.b.com`\n",
+                 "<p><a href=\"/x\">www.a.com</a> <code>www.b.com</code></p>");
+        // spans stay on the source when entities before a link shortened the text
+        let input = "[x]: /u\n\n[x] www.a.com &copy; \\* www.b.com\n";
+        let doc = crate::parse(input).unwrap();
+        let links: Vec<_> = match &doc.blocks[..] {
+            [Block::PlainParagraph(p)] => p.text.nodes.iter().filter_map(|x| match x {
+                Inline::Url(u) => Some(&input[u.span.start.offset..u.span.end.offset]),
+                _ => None,
+            }).collect(),
+            x => panic!("unexpected {:?}", x),
+        };
+        assert_eq!(links, ["[x]", "www.a.com", "www.b.com"]);
     }
-}
 
-/// todo: does not support complex structural quotes yet
-fn quote(text: &InnerBuffer, pos: usize) -> Option<(Quote, usize)> {
-    let mut pos = pos;
-    let is_quote_prefix = |pz: usize| -> Option<usize> {
-        let mut pz = pz;
-        let ch = character(&text, pz);
-        pz += 1;
-        if ch == '>' as InnerByte {
-            return Some(pz + 1);
-        }
-        return None;
-    };
-    let mut quote = Quote { list: vec![] };
-    loop {
-        match is_quote_prefix(pos) {
-            Some(p) => pos = p,
-            None => break
-        }
-        match inline(text, pos, false, "", true, true, true) {
-            Some((x, p)) => {
-                quote.list.push(Box::new(x));
-                pos = p;
-            }
-            None => break
-        }
-    }
-    if quote.len() > 0 {
-        return Some((quote, pos));
-    } else {
-        return None;
+    #[test]
+    fn footnotes() {
+        let doc = crate::parse("a[^x] ^[b] [^X]\n\n[^x]: c\n\n    d\n[^y]: unused\n").unwrap();
+        let numbers: Vec<_> = doc.footnotes.iter().map(|x| (x.number, x.label.as_str(), x.references)).collect();
+        assert_eq!(numbers, [(1, "x", 2), (2, "", 1)]);
+        assert_eq!(doc.footnotes[0].blocks.len(), 2);
+
+        // numbered by first reference, not by definition
+        test_gfm("[^2] [^1]\n\n[^1]: one\n[^2]: two\n",
+                 "<p><sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup> \
+                  <sup class=\"footnote-ref\"><a href=\"#fn-2\" id=\"fnref-2\">2</a></sup></p>\
+                  <section class=\"footnotes\"><ol>\
+                  <li id=\"fn-1\"><p>two <a href=\"#fnref-1\" class=\"footnote-backref\">\u{21a9}</a></p></li>\
+                  <li id=\"fn-2\"><p>one <a href=\"#fnref-2\" class=\"footnote-backref\">\u{21a9}</a></p></li>\
+                  </ol></section>");
+        // every reference gets its own link back
+        test_gfm("[^a][^a]\n\n[^a]: x\n",
+                 "<p><sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup>\
+                  <sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1-2\">1</a></sup></p>\
+                  <section class=\"footnotes\"><ol><li id=\"fn-1\"><p>x \
+                  <a href=\"#fnref-1\" class=\"footnote-backref\">\u{21a9}</a> \
+                  <a href=\"#fnref-1-2\" class=\"footnote-backref\">\u{21a9}<sup>2</sup></a></p></li></ol></section>");
+        // an inline footnote may hold links, and is numbered before the ones inside it
+        let doc = crate::parse("^[see [a](b) ^[c]]\n").unwrap();
+        assert_eq!(doc.footnotes.len(), 2);
+        assert!(matches!(&doc.footnotes[0].blocks[0], Block::PlainParagraph(p) if p.text.nodes.len() == 4));
+        // a reference needs a definition, and footnotes are an extension
+        test_gfm("[^a] ^b\n", "<p>[^a] ^b</p>");
+        test("a[^x]\n\n[^x]: b\n", "<p>a<a href=\"b\">^x</a></p>");
+        test("^[a]\n", "<p>^[a]</p>");
     }
-}
 
-fn code_block(text: &InnerBuffer, pos: usize) -> Option<(CodeBlock, usize)> {
-    let mut pos = pos;
-    while character(&text, pos).is_ascii_whitespace() {
-        pos += 1;
-    }
-    if is(&text, pos, "```") {
-        let mut pos = check_eat(&text, pos, "```", 1);
-        let lang;
-        match get_word(&text, pos) {
-            Some((s, p)) => {
-                lang = s;
-                pos = p;
-            }
-            None => lang = vec![]
-        }
-        let result = get_until(&text, pos, "```", true);
-        return Some((CodeBlock { language: lang, text: result.text }, result.pos));
+    #[test]
+    fn footnote_list_starts_at_first_number() {
+        use crate::Renderer;
+        // documents rendered into one page number their footnotes on from
+        // the ones before, and the list shows the numbers of its ids
+        let mut doc = crate::parse("a[^x]\n\n[^x]: b\n").unwrap();
+        doc.footnotes[0].number = 3;
+        let mut buf: Vec<u8> = vec![];
+        crate::HtmlRenderer::new(&Options::default()).render_footnotes(&doc.footnotes, &mut buf);
+        assert_eq!(normalize(&String::from_utf8(buf).unwrap()),
+                   "<section class=\"footnotes\"><ol start=\"3\"><li id=\"fn-3\"><p>b \
+                    <a href=\"#fnref-3\" class=\"footnote-backref\">\u{21a9}</a></p></li></ol></section>");
     }
-    return None;
-}
 
-fn math_display(text: &InnerBuffer, pos: usize) -> Option<(MathDisplay, usize)> {
-    if is(&text, pos, "$$") {
-        let pos = check_eat(&text, pos, "$$", 1);
-        let result = get_until(&text, pos, "$$", true);
-        return Some((MathDisplay { formula: result.text }, result.pos));
+    #[test]
+    fn math_extension() {
+        let html = crate::to_html("$$\nx^2\n$$\n\ncosts $5 and $10, $a+b$\n").unwrap();
+        assert_eq!(html, "<pre class=\"lang-math-display\">x^2</pre>\n\
+                          <p>costs $5 and $10, <div class=\"lang-math-inline\">a+b</div></p>\n");
     }
-    return None;
-}
 
-fn inline(text: &InnerBuffer, pos: usize, multi_lines: bool, stop_at: &str, enable_bold: bool, enable_italic: bool, enable_deleted: bool) -> Option<(Text, usize)> {
-    let mut pos = pos;
-    let mut text_node = Text { nodes: vec![] };
-    let mut cond = true;
-    while cond {
-        cond = false;
-//            show_slice(text: &InnerBuffer.raw_text, pos, self.raw_text.len(), pos, pos + 5);
-        if is(&text, pos, stop_at) {
-            pos += stop_at.len();
-            break;
-        }
-        if enable_bold {
-            match bold(&text, pos, enable_italic, enable_deleted) {
-                Some((b, p)) => {
-                    text_node.nodes.push(Box::new(b));
-                    pos = p;
-                    cond = true;
-                    continue;
-                }
-                None => {}
-            }
-        }
-        if enable_italic {
-            match italic(&text, pos, enable_bold, enable_deleted) {
-                Some((i, p)) => {
-                    text_node.nodes.push(Box::new(i));
-                    pos = p;
-                    cond = true;
-                    continue;
-                }
-                None => {}
-            }
-        }
-        if enable_deleted {
-            match deleted(&text, pos, enable_bold, enable_italic) {
-                Some((d, p)) => {
-                    text_node.nodes.push(Box::new(d));
-                    pos = p;
-                    cond = true;
-                    continue;
-                }
-                None => {}
-            }
-        }
-        match code_inline(&text, pos) {
-            Some((c, p)) => {
-                text_node.nodes.push(Box::new(c));
-                pos = p;
-                cond = true;
-                continue;
-            }
-            None => {}
-        }
-        match math_inline(&text, pos) {
-            Some((m, p)) => {
-                text_node.nodes.push(Box::new(m));
-                pos = p;
-                cond = true;
-                continue;
-            }
-            None => {}
-        }
-        match image(&text, pos) {
-            Some((i, p)) => {
-                text_node.nodes.push(Box::new(i));
-                pos = p;
-                cond = true;
-                continue;
+    #[test]
+    fn no_input_panics() {
+        let samples = [
+            "", "#", "#######", "```", "```rust", "$$", "$", "`", "**", "*",
+            "~~", "![", "![a](", "![a](b c", "[a](b \"c\")", ">", "> ",
+            "1.", "1. ", "- ", "=", "\0", "\r\n", "\u{FFFD}", "é*ü*",
+            "|a|\n|-|", "- [x]", "&#0;", "<!--", "www.", "a@b.",
+            "^[", "[^a]:", "[^a]\n\n[^a]:", "^[[^a]]\n[^a]: ^[b]",
+        ];
+        let options = [Options::default(), Options::commonmark()];
+        for s in samples.iter() {
+            for options in &options {
+                let _ = crate::to_html_with_options(s, options);
             }
-            None => {}
         }
-        match url(&text, pos) {
-            Some((u, p)) => {
-                text_node.nodes.push(Box::new(u));
-                pos = p;
-                cond = true;
-                continue;
+        // a cheap deterministic fuzzer over markdown-significant characters
+        // and the tokens that start or end blocks
+        let mut tokens: Vec<String> = "#*_~`$[]()!>-=+. 1\n\tä\"|:<&@w\\^".chars().map(String::from).collect();
+        tokens.extend([
+            "[a]: /u", "[a]", "](/u)", "\n---", "\n===", "\n-", "\n\n", "    ", "> ", "- ", "1. ",
+            "|-|", "| a |", "\\|", "```", "~~~", "$$", "***", "[^a]: ", "^[", "<div>", "-->",
+            "www.a.com", "&amp;", "- [x] ",
+        ].iter().map(|x| x.to_string()));
+        let mut seed: u32 = 0x2545_f491;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+        for _ in 0..20000 {
+            let mut s = String::new();
+            for _ in 0..next() % 24 {
+                s.push_str(&tokens[next() % tokens.len()]);
             }
-            None => {}
-        }
-        if !is_eof(&text, pos) {
-            let c = character(&text, pos);
-
-            if !multi_lines && c == '\n' as InnerByte {
-                pos += 1;
-                break;
+            for options in &options {
+                let _ = crate::to_html_with_options(&s, options);
             }
-            text_node.nodes.push(Box::new(c));
-            pos += 1;
-            cond = true;
         }
     }
 
-    return Some((text_node, pos));
-}
-
-fn bold(text: &InnerBuffer, pos: usize, italic: bool, del: bool) -> Option<(Bold, usize)> {
-    if is(&text, pos, "**") {
-        let pos = check_eat(&text, pos, "**", 1);
-        match inline(&text, pos, false, "**", false, italic, del) {
-            Some((x, y)) => {
-                if x.len() > 0 {
-                    return Some((Bold { text: Box::new(x) }, y));
-                }
-            }
-            None => {}
+    #[test]
+    fn deep_nesting_does_not_overflow() {
+        let n = 20000;
+        let inputs = [
+            "> ".repeat(n) + "a",
+            "- ".repeat(n) + "a",
+            "*".repeat(n) + "a" + &"*".repeat(n),
+            "![".repeat(n) + "a" + &"](b)".repeat(n),
+        ];
+        for s in inputs.iter() {
+            let html = crate::to_html(s).unwrap();
+            assert!(html.contains('a'));
         }
     }
-    return None;
-}
 
-fn italic(text: &InnerBuffer, pos: usize, bold: bool, del: bool) -> Option<(Italic, usize)> {
-    if is(&text, pos, "*") {
-        let pos = check_eat(&text, pos, "*", 1);
-        match inline(&text, pos, false, "*", bold, false, del) {
-            Some((x, y)) => {
-                if x.len() > 0 {
-                    return Some((Italic { text: Box::new(x) }, y));
-                }
-            }
-            None => {}
-        }
+    #[test]
+    fn long_autolinks_take_linear_time() {
+        // trimming one unbalanced parenthesis at a time used to recount all
+        // of them, which took minutes here
+        let n = 200_000;
+        let html = crate::to_html(&("www.a.com/(".to_string() + &")".repeat(n))).unwrap();
+        assert!(html.starts_with("<p><a href=\"http://www.a.com/()\">www.a.com/()</a>)"));
+        assert_eq!(html.matches(')').count(), 1 + n);
     }
-    return None;
-}
 
-fn deleted(text: &InnerBuffer, pos: usize, bold: bool, italic: bool) -> Option<(Deleted, usize)> {
-    if is(&text, pos, "~~") {
-        let pos = check_eat(&text, pos, "~~", 1);
-        match inline(&text, pos, false, "~~", bold, italic, false) {
-            Some((x, y)) => {
-                if x.len() > 0 {
-                    return Some((Deleted { text: Box::new(x) }, y));
-                }
+    #[test]
+    fn many_definitions_take_linear_time() {
+        let n = 200_000;
+        let html = crate::to_html(&("[a]: /u\n".repeat(n) + "[a]\n")).unwrap();
+        assert_eq!(html, "<p><a href=\"/u\">a</a></p>\n");
+    }
+
+    /// The first example of every section of the GFM spec, in order.
+    const SECTIONS: &[(&str, usize)] = &[
+        ("Tabs", 1),
+        ("Precedence", 12),
+        ("Thematic breaks", 13),
+        ("ATX headings", 32),
+        ("Setext headings", 50),
+        ("Indented code blocks", 77),
+        ("Fenced code blocks", 89),
+        ("HTML blocks", 118),
+        ("Link reference definitions", 161),
+        ("Paragraphs", 189),
+        ("Blank lines", 197),
+        ("Tables (extension)", 198),
+        ("Block quotes", 206),
+        ("List items", 231),
+        ("Task list items (extension)", 279),
+        ("Lists", 281),
+        ("Inlines", 307),
+        ("Backslash escapes", 308),
+        ("Entity and numeric character references", 321),
+        ("Code spans", 338),
+        ("Emphasis and strong emphasis", 360),
+        ("Strikethrough (extension)", 491),
+        ("Links", 493),
+        ("Images", 580),
+        ("Autolinks", 602),
+        ("Autolinks (extension)", 621),
+        ("Raw HTML", 632),
+        ("Disallowed Raw HTML (extension)", 653),
+        ("Hard line breaks", 654),
+        ("Soft line breaks", 669),
+        ("Textual content", 671),
+    ];
+
+    /// Reads the string literal starting at the `"` at `pos`.
+    fn literal(s: &str, pos: usize) -> (String, usize) {
+        let mut out = String::new();
+        let mut chars = s[pos + 1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return (out, pos + 1 + i + 1),
+                '\\' => match chars.next().map(|x| x.1) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some(x) => out.push(x),
+                    None => break,
+                },
+                _ => out.push(c),
             }
-            None => {}
-        }
-    }
-    return None;
-}
-
-fn code_inline(text: &InnerBuffer, pos: usize) -> Option<(CodeInline, usize)> {
-    if is(&text, pos, "`") {
-        let pos = check_eat(&text, pos, "`", 1);
-        let result = get_until(&text, pos, "`", false);
-        if result.text.len() > 0 {
-            return Some((CodeInline { code: result.text }, result.pos));
-        }
-    }
-    return None;
-}
-
-fn math_inline(text: &InnerBuffer, pos: usize) -> Option<(MathInline, usize)> {
-    if is(&text, pos, "$") {
-        let pos = check_eat(&text, pos, "$", 1);
-        let result = get_until(&text, pos, "`", false);
-        if result.text.len() > 0 {
-            return Some((MathInline { formula: result.text }, result.pos));
-        }
-    }
-    return None;
-}
-
-fn image(text: &InnerBuffer, pos: usize) -> Option<(Image, usize)> {
-    let mut pos = pos;
-
-    if !is(&text, pos, "![") {
-        return None;
-    }
-    pos = check_eat(&text, pos, "![", 1);
-    let result = get_until(&text, pos, "]", false);
-    if !result.reached_target {
-        return None;
-    }
-    let (alt, mut pos) = (result.text, result.pos);
-
-    if !is(&text, pos, "(") {
-        return None;
-    }
-    pos = check_eat(&text, pos, "(", 1);
-
-    let result2 =
-        get_until_pattern(&text, pos,
-                          |x|
-                              if is(&text, x, " ") || is(&text, x, ")") {
-                                  Some(x + 1)
-                              } else {
-                                  None
-                              }
-                          , false);
-    if !result2.reached_target {
-        return None;
-    }
-    let (url, mut pos) = (result2.text, result2.pos - 1);
-
-    let title;
-    if is(&text, pos, " ") {
-        pos = check_eat(&text, pos, " ", 1);
-        let result = get_until(&text, pos, ")", false);
-        if !result.reached_target {
-            return None;
         }
-        title = result.text;
-        pos = pos;
-    } else {
-        title = vec![];
-    }
-
-    if !is(&text, pos, ")") {
-        return None;
+        panic!("unterminated literal at {}", pos);
     }
 
-    return Some((Image { alt, url, title }, check_eat(&text, pos, ")", 1)));
-}
-
-fn url(text: &InnerBuffer, pos: usize) -> Option<(Url, usize)> {
-    let mut pos = pos;
-
-    if !is(&text, pos, "[") {
-        return None;
-    }
-    pos = check_eat(&text, pos, "[", 1);
-    let result = get_until(&text, pos, "]", false);
-    if !result.reached_target {
-        return None;
-    }
-    let (buf, mut pos) = (result.text, result.pos);
-    if !is(&text, pos, "(") {
-        return None;
-    }
-    pos = check_eat(&text, pos, "(", 1);
-    let result2 =
-        get_until_pattern(&text, pos,
-                          |x|
-                              if is(&text, x, " ") || is(&text, x, ")") {
-                                  Some(x + 1)
-                              } else {
-                                  None
-                              }
-                          , false);
-    if !result2.reached_target {
-        return None;
-    }
-    let (url, mut pos) = (result2.text, result2.pos - 1);
-    let title;
-    if is(&text, pos, " ") {
-        pos = check_eat(&text, pos, " ", 1);
-        let result = get_until(&text, pos, ")", false);
-        if !result.reached_target {
-            return None;
+    /// Prints how many examples of every spec section pass; run with
+    /// `cargo test spec_pass_rate -- --nocapture` to see the table.
+    #[test]
+    fn spec_pass_rate() {
+        let source = include_str!("gfm_tests.in");
+        let mut passed = vec![0; SECTIONS.len()];
+        let mut total = vec![0; SECTIONS.len()];
+        let mut rest = source;
+        while let Some(at) = rest.find("fn gfm_test_") {
+            rest = &rest[at + "fn gfm_test_".len()..];
+            let number: usize = rest[..3].parse().unwrap();
+            let call = rest.find("test").unwrap();
+            let options = if rest[call..].starts_with("test_gfm(") {
+                Options::default()
+            } else {
+                Options::commonmark()
+            };
+            let (raw, end) = literal(rest, rest[call..].find('"').unwrap() + call);
+            let (fnl, _) = literal(rest, rest[end..].find('"').unwrap() + end);
+            let section = SECTIONS.iter().rposition(|x| x.1 <= number).unwrap();
+            total[section] += 1;
+            if normalize(&easy_parse(&raw, &options)) == fnl {
+                passed[section] += 1;
+            }
         }
-        title = result.text;
-        pos = pos;
-    } else {
-        title = vec![];
-    }
-    if !is(&text, pos, ")") {
-        return None;
-    }
-
-    return Some((Url { text: buf, url, title }, check_eat(text, pos, ")", 1)));
-}
-
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn easy_parse(s: &str) -> String {
-        let input = s.to_string();
-        let mut buf: Vec<u8> = vec![];
-        parse(&input.into_bytes(), &mut buf);
-        return String::from_utf8(buf).unwrap();
-    }
-
-    fn test(raw: &str, fnl: &str) {
-        let rlt = easy_parse(raw);
-        if &rlt != fnl {
-            panic!("Test failed. \nInput:\n{}\nResult:\n{}\nShould be:\n{}\n",
-                   raw, rlt, fnl
-            )
+        for (i, (name, _)) in SECTIONS.iter().enumerate() {
+            let rate = 100.0 * passed[i] as f64 / total[i] as f64;
+            println!("{:<42} {:>3}/{:<3} {:>6.1}%", name, passed[i], total[i], rate);
         }
+        let (passed, total) = (passed.iter().sum::<usize>(), total.iter().sum::<usize>());
+        println!("{:<42} {:>3}/{:<3} {:>6.1}%", "Total", passed, total, 100.0 * passed as f64 / total as f64);
+        assert_eq!(total, 673);
     }
-    #[test]
-    fn start_test(){}
 
     include!("gfm_tests.in");
-}
\ No newline at end of file
+}
@@ -0,0 +1,1307 @@
+//! The block phase.
+//!
+//! Every line first walks down the open blocks, each of which consumes the
+//! markers it needs to continue (`>` for a quote, the indentation of a list
+//! item, ...). Whatever is left can start new blocks, and the rest of the
+//! line lands in the innermost open leaf, or lazily continues a paragraph.
+
+use super::inline::{self, Footnotes, RefMap};
+use super::{Content, MAX_NESTING};
+use crate::ast::*;
+use crate::options::Options;
+use crate::position::LineIndex;
+
+const CODE_INDENT: usize = 4;
+/// How many missing cells of short rows one table may fill in, as in
+/// cmark-gfm; past that, lines are no longer taken as rows so that a wide
+/// header can't make the output grow with columns times rows.
+const MAX_AUTOCOMPLETED_CELLS: usize = 0x80000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ListData {
+    ordered: bool,
+    /// the bullet, or the delimiter after the number
+    marker: u8,
+    start: u32,
+    /// the indentation of the marker
+    marker_offset: usize,
+    /// the width of the marker and the spaces after it
+    padding: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fence {
+    ch: u8,
+    len: usize,
+    /// the indentation of the opening fence, stripped from the content
+    offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Document,
+    Quote,
+    List(ListData),
+    Item(ListData),
+    Paragraph,
+    Heading(u8, HeadingStyle),
+    /// the marker character
+    ThematicBreak(u8),
+    /// `None` for an indented code block
+    CodeBlock(Option<Fence>),
+    /// one of the seven start conditions of the spec
+    HtmlBlock(u8),
+    Table(Vec<Alignment>),
+    MathDisplay,
+    /// a `[^label]: ...` definition, holding the label as written
+    Footnote(String),
+}
+
+impl Kind {
+    fn can_contain(&self, child: &Kind) -> bool {
+        match self {
+            Kind::Document | Kind::Quote | Kind::Item(_) | Kind::Footnote(_) => !matches!(child, Kind::Item(_)),
+            Kind::List(_) => matches!(child, Kind::Item(_)),
+            _ => false,
+        }
+    }
+
+    fn accepts_lines(&self) -> bool {
+        matches!(self, Kind::Paragraph | Kind::CodeBlock(_) | Kind::HtmlBlock(_) | Kind::Table(_) | Kind::MathDisplay)
+    }
+}
+
+struct Node {
+    kind: Kind,
+    parent: usize,
+    /// how many blocks this one is nested in
+    depth: usize,
+    children: Vec<usize>,
+    open: bool,
+    last_line_blank: bool,
+    last_line_checked: bool,
+    start: usize,
+    end: usize,
+    start_line: usize,
+    content: Content,
+    /// the rows of a table, header first
+    rows: Vec<Content>,
+    /// how many cells the rows of a table lack
+    autocompleted: usize,
+    /// the info string of a fenced code block
+    info: String,
+    tight: bool,
+}
+
+/// How the continuation of an open block matched a line.
+enum Continue {
+    Matched,
+    Failed,
+    /// the line closed the block and nothing is left of it
+    Consumed,
+}
+
+/// What a block start found.
+enum Start {
+    None,
+    Container,
+    Leaf,
+}
+
+pub(super) struct Tree {
+    nodes: Vec<Node>,
+    refmap: RefMap,
+}
+
+struct Parser<'a> {
+    options: &'a Options,
+    nodes: Vec<Node>,
+    refmap: RefMap,
+    tip: usize,
+    old_tip: usize,
+    last_matched: usize,
+    all_closed: bool,
+    line: &'a str,
+    line_start: usize,
+    line_number: usize,
+    offset: usize,
+    column: usize,
+    next_nonspace: usize,
+    next_nonspace_column: usize,
+    indent: usize,
+    indented: bool,
+    blank: bool,
+    partially_consumed_tab: bool,
+}
+
+pub(super) fn parse(input: &str, options: &Options) -> Tree {
+    let mut p = Parser {
+        options,
+        nodes: vec![],
+        refmap: RefMap::new(),
+        tip: 0,
+        old_tip: 0,
+        last_matched: 0,
+        all_closed: true,
+        line: "",
+        line_start: 0,
+        line_number: 0,
+        offset: 0,
+        column: 0,
+        next_nonspace: 0,
+        next_nonspace_column: 0,
+        indent: 0,
+        indented: false,
+        blank: false,
+        partially_consumed_tab: false,
+    };
+    p.new_node(Kind::Document, 0, 0);
+    let bytes = input.as_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        let mut end = start;
+        while end < bytes.len() && bytes[end] != b'\n' && bytes[end] != b'\r' {
+            end += 1;
+        }
+        p.incorporate_line(&input[start..end], start);
+        start = if bytes[end..].starts_with(b"\r\n") { end + 2 } else { end + 1 };
+    }
+    while p.tip != 0 {
+        p.finalize(p.tip);
+    }
+    p.finalize(0);
+    p.nodes[0].end = input.len();
+    Tree { nodes: p.nodes, refmap: p.refmap }
+}
+
+fn is_space_or_tab(c: Option<u8>) -> bool {
+    c == Some(b' ') || c == Some(b'\t')
+}
+
+/// Whether a line starting with `c` could open a block at all.
+fn maybe_special(c: u8) -> bool {
+    b"#`~*+_=<>-|:$[".contains(&c) || c.is_ascii_digit()
+}
+
+fn is_thematic_break(s: &[u8]) -> bool {
+    let c = match s.first() {
+        Some(&c) if c == b'*' || c == b'-' || c == b'_' => c,
+        _ => return false,
+    };
+    let mut count = 0;
+    for &x in s {
+        if x == c {
+            count += 1;
+        } else if x != b' ' && x != b'\t' {
+            return false;
+        }
+    }
+    count >= 3
+}
+
+/// The length of the label of a footnote definition, `s` being what follows
+/// its `[^`.
+fn footnote_label(s: &[u8]) -> Option<usize> {
+    let len = s.iter().take_while(|&&x| !x.is_ascii_whitespace() && x != b'[' && x != b']').count();
+    if len > 0 && s[len..].starts_with(b"]:") {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The level of a setext heading underline.
+fn setext_level(s: &[u8]) -> Option<u8> {
+    let c = *s.first()?;
+    if c != b'=' && c != b'-' {
+        return None;
+    }
+    let run = s.iter().take_while(|&&x| x == c).count();
+    if s[run..].iter().all(|&x| x == b' ' || x == b'\t') {
+        Some(if c == b'=' { 1 } else { 2 })
+    } else {
+        None
+    }
+}
+
+/// The length of the closing fence of `ch` that `s` is, or 0.
+fn closing_fence(s: &[u8], ch: u8) -> usize {
+    let run = s.iter().take_while(|&&x| x == ch).count();
+    if run >= 3 && s[run..].iter().all(|&x| x == b' ' || x == b'\t') {
+        run
+    } else {
+        0
+    }
+}
+
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "base", "basefont", "blockquote", "body", "caption", "center",
+    "col", "colgroup", "dd", "details", "dialog", "dir", "div", "dl", "dt", "fieldset",
+    "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2", "h3", "h4", "h5",
+    "h6", "head", "header", "hr", "html", "iframe", "legend", "li", "link", "main", "menu",
+    "menuitem", "nav", "noframes", "ol", "optgroup", "option", "p", "param", "section", "source",
+    "summary", "table", "tbody", "td", "tfoot", "th", "thead", "title", "tr", "track", "ul",
+];
+
+fn starts_with_ignore_case(s: &[u8], prefix: &str) -> bool {
+    s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+/// Whether `s` meets start condition `kind` of an HTML block.
+fn html_block_start(s: &[u8], kind: u8) -> bool {
+    match kind {
+        1 => ["<script", "<pre", "<style"].iter().any(|tag| {
+            starts_with_ignore_case(s, tag)
+                && matches!(s.get(tag.len()), None | Some(b' ') | Some(b'\t') | Some(b'>'))
+        }),
+        2 => s.starts_with(b"<!--"),
+        3 => s.starts_with(b"<?"),
+        4 => s.len() > 2 && s.starts_with(b"<!") && s[2].is_ascii_uppercase(),
+        5 => s.starts_with(b"<![CDATA["),
+        6 => {
+            let name_start = if s.starts_with(b"</") { 2 } else { 1 };
+            let name_end = name_start + s[name_start..].iter().take_while(|x| x.is_ascii_alphanumeric()).count();
+            let name = String::from_utf8_lossy(&s[name_start..name_end]).to_ascii_lowercase();
+            BLOCK_TAGS.contains(&&name[..])
+                && match s.get(name_end) {
+                    None | Some(b' ') | Some(b'\t') | Some(b'>') => true,
+                    Some(b'/') => s.get(name_end + 1) == Some(&b'>'),
+                    _ => false,
+                }
+        }
+        7 => {
+            let end = match inline::open_tag(s, 0).or_else(|| inline::closing_tag(s, 0)) {
+                Some(end) => end,
+                None => return false,
+            };
+            s[end..].iter().all(|&x| x == b' ' || x == b'\t')
+        }
+        _ => false,
+    }
+}
+
+/// Whether `line` ends HTML block `kind`.
+fn html_block_end(line: &str, kind: u8) -> bool {
+    let lower = line.to_ascii_lowercase();
+    match kind {
+        1 => lower.contains("</script>") || lower.contains("</pre>") || lower.contains("</style>"),
+        2 => line.contains("-->"),
+        3 => line.contains("?>"),
+        4 => line.contains('>'),
+        5 => line.contains("]]>"),
+        _ => false,
+    }
+}
+
+/// Strips `(\n *)+$`, keeping a final line break if `keep_newline`.
+fn strip_blank_lines(s: &mut String, keep_newline: bool) {
+    let mut end = s.len();
+    loop {
+        let trimmed = s[..end].trim_end_matches(' ');
+        if !trimmed.ends_with('\n') {
+            break;
+        }
+        end = trimmed.len() - 1;
+    }
+    if end < s.len() {
+        s.truncate(end);
+        if keep_newline {
+            s.push('\n');
+        }
+    }
+}
+
+/// A code block with the language, the attributes and the highlighted line
+/// ranges of info string `info`, as in `rust title="main.rs" {1,3-5}`.
+fn parse_info(info: &str) -> CodeBlock {
+    let language_end = info.find(|c: char| c.is_whitespace() || c == '{').unwrap_or(info.len());
+    let mut attributes = vec![];
+    let mut highlights = vec![];
+    let mut rest = &info[language_end..];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(ranges) = rest.strip_prefix('{') {
+            let end = ranges.find('}').unwrap_or(ranges.len());
+            for range in ranges[..end].split(',') {
+                let (first, last) = range.split_once('-').unwrap_or((range, range));
+                if let (Ok(first), Ok(last)) = (first.trim().parse(), last.trim().parse()) {
+                    if 0 < first && first <= last {
+                        highlights.push((first, last));
+                    }
+                }
+            }
+            rest = ranges.get(end + 1..).unwrap_or("");
+            continue;
+        }
+        let key_end = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '{').unwrap_or(rest.len());
+        let key = rest[..key_end].to_string();
+        rest = &rest[key_end..];
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let (raw, next) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted.find('"').unwrap_or(quoted.len());
+                    (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+                }
+                None => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+            };
+            value = raw.to_string();
+            rest = next;
+        }
+        if !key.is_empty() {
+            attributes.push((key, value));
+        }
+    }
+    CodeBlock { language: info[..language_end].to_string(), attributes, highlights, ..CodeBlock::default() }
+}
+
+/// The cell ranges of a table row, trimmed, leaving out the outer pipes.
+fn split_row(s: &str) -> Vec<(usize, usize)> {
+    let b = s.as_bytes();
+    let mut p = b.iter().take_while(|x| x.is_ascii_whitespace()).count();
+    if b.get(p) == Some(&b'|') {
+        p += 1;
+    }
+    let mut cells = vec![];
+    let mut start = p;
+    while p < b.len() {
+        match b[p] {
+            b'\\' => p += 2,
+            b'|' => {
+                cells.push((start, p));
+                p += 1;
+                start = p;
+            }
+            _ => p += 1,
+        }
+    }
+    let end = b.len();
+    if start < end && !s[start..].trim().is_empty() {
+        cells.push((start, end));
+    }
+    cells
+        .into_iter()
+        .map(|(start, end)| {
+            let start = start.min(end);
+            let cell = &s[start..end];
+            let lead = cell.len() - cell.trim_start().len();
+            (start + lead, start + cell.trim_end().len().max(lead))
+        })
+        .collect()
+}
+
+/// The column alignments of a table delimiter row like `| :-- | --: |`.
+fn delimiter_row(s: &str) -> Option<Vec<Alignment>> {
+    let cells = split_row(s);
+    if cells.is_empty() {
+        return None;
+    }
+    let mut alignments = vec![];
+    for (start, end) in cells {
+        let cell = &s.as_bytes()[start..end];
+        let left = cell.first() == Some(&b':');
+        let right = cell.len() > 1 && cell.last() == Some(&b':');
+        let dashes = &cell[left as usize..cell.len() - right as usize];
+        if dashes.is_empty() || dashes.iter().any(|&x| x != b'-') {
+            return None;
+        }
+        alignments.push(match (left, right) {
+            (true, true) => Alignment::Center,
+            (true, false) => Alignment::Left,
+            (false, true) => Alignment::Right,
+            (false, false) => Alignment::None,
+        });
+    }
+    Some(alignments)
+}
+
+impl<'a> Parser<'a> {
+    fn new_node(&mut self, kind: Kind, start: usize, parent: usize) -> usize {
+        let depth = self.nodes.get(parent).map_or(0, |x| x.depth + 1);
+        self.nodes.push(Node {
+            kind,
+            parent,
+            depth,
+            children: vec![],
+            open: true,
+            last_line_blank: false,
+            last_line_checked: false,
+            start,
+            end: start,
+            start_line: self.line_number,
+            content: Content::default(),
+            rows: vec![],
+            autocompleted: 0,
+            info: String::new(),
+            tight: true,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn peek(&self, pos: usize) -> Option<u8> {
+        self.line.as_bytes().get(pos).copied()
+    }
+
+    fn find_next_nonspace(&mut self) {
+        let bytes = self.line.as_bytes();
+        let mut i = self.offset;
+        let mut cols = self.column;
+        while i < bytes.len() {
+            match bytes[i] {
+                b' ' => cols += 1,
+                b'\t' => cols += 4 - (cols % 4),
+                _ => break,
+            }
+            i += 1;
+        }
+        self.blank = i == bytes.len();
+        self.next_nonspace = i;
+        self.next_nonspace_column = cols;
+        self.indent = cols - self.column;
+        self.indented = self.indent >= CODE_INDENT;
+    }
+
+    fn advance_next_nonspace(&mut self) {
+        self.offset = self.next_nonspace;
+        self.column = self.next_nonspace_column;
+        self.partially_consumed_tab = false;
+    }
+
+    /// Moves over `count` characters, or `count` columns if `columns`, in
+    /// which case a tab may be consumed only partially.
+    fn advance_offset(&mut self, mut count: usize, columns: bool) {
+        let bytes = self.line.as_bytes();
+        while count > 0 && self.offset < bytes.len() {
+            if bytes[self.offset] == b'\t' {
+                let to_tab = 4 - (self.column % 4);
+                if columns {
+                    self.partially_consumed_tab = to_tab > count;
+                    let advance = to_tab.min(count);
+                    self.column += advance;
+                    if !self.partially_consumed_tab {
+                        self.offset += 1;
+                    }
+                    count -= advance;
+                } else {
+                    self.partially_consumed_tab = false;
+                    self.column += to_tab;
+                    self.offset += 1;
+                    count -= 1;
+                }
+            } else {
+                self.partially_consumed_tab = false;
+                self.offset += 1;
+                self.column += 1;
+                count -= 1;
+            }
+        }
+    }
+
+    fn advance_to_end(&mut self) {
+        self.offset = self.line.len();
+        self.partially_consumed_tab = false;
+    }
+
+    /// Adds the rest of the line to the tip.
+    fn add_line(&mut self) {
+        let tip = self.tip;
+        if self.partially_consumed_tab {
+            // the rest of a tab we are in the middle of becomes spaces
+            self.offset += 1;
+            let spaces = 4 - (self.column % 4);
+            let source = self.line_start + self.offset - 1;
+            self.nodes[tip].content.push(&"    "[..spaces], source);
+        }
+        let rest = &self.line[self.offset..];
+        let source = self.line_start + self.offset;
+        let content = &mut self.nodes[tip].content;
+        content.push(rest, source);
+        content.text.push('\n');
+    }
+
+    fn add_child(&mut self, kind: Kind, offset: usize) -> usize {
+        while !self.nodes[self.tip].kind.can_contain(&kind) {
+            self.finalize(self.tip);
+        }
+        let parent = self.tip;
+        let id = self.new_node(kind, self.line_start + offset, parent);
+        self.nodes[parent].children.push(id);
+        self.tip = id;
+        id
+    }
+
+    fn close_unmatched_blocks(&mut self) {
+        if !self.all_closed {
+            while self.old_tip != self.last_matched {
+                let parent = self.nodes[self.old_tip].parent;
+                self.finalize(self.old_tip);
+                self.old_tip = parent;
+            }
+            self.all_closed = true;
+        }
+    }
+
+    /// Extends the span of `node` and its ancestors to the end of the line.
+    fn extend(&mut self, mut node: usize) {
+        let end = self.line_start + self.line.len();
+        loop {
+            self.nodes[node].end = end;
+            if node == 0 {
+                break;
+            }
+            node = self.nodes[node].parent;
+        }
+    }
+
+    fn incorporate_line(&mut self, line: &'a str, line_start: usize) {
+        self.line = line;
+        self.line_start = line_start;
+        self.line_number += 1;
+        self.offset = 0;
+        self.column = 0;
+        self.blank = false;
+        self.partially_consumed_tab = false;
+        self.old_tip = self.tip;
+
+        let mut container = 0;
+        while let Some(&last) = self.nodes[container].children.last() {
+            if !self.nodes[last].open {
+                break;
+            }
+            container = last;
+            self.find_next_nonspace();
+            match self.continues(container) {
+                Continue::Matched => {}
+                Continue::Failed => {
+                    container = self.nodes[container].parent;
+                    break;
+                }
+                Continue::Consumed => {
+                    self.extend(container);
+                    return;
+                }
+            }
+        }
+
+        self.all_closed = container == self.old_tip;
+        self.last_matched = container;
+
+        let kind = &self.nodes[container].kind;
+        let mut matched_leaf = kind.accepts_lines() && !matches!(kind, Kind::Paragraph | Kind::Table(_));
+        while !matched_leaf {
+            self.find_next_nonspace();
+            if !self.indented && !self.peek(self.next_nonspace).is_some_and(maybe_special) {
+                self.advance_next_nonspace();
+                break;
+            }
+            match self.block_start(container) {
+                Start::Container => container = self.tip,
+                Start::Leaf => {
+                    container = self.tip;
+                    matched_leaf = true;
+                }
+                Start::None => {
+                    self.advance_next_nonspace();
+                    break;
+                }
+            }
+        }
+
+        if !self.all_closed && !self.blank && self.nodes[self.tip].kind == Kind::Paragraph {
+            // a lazy continuation line
+            self.add_line();
+            self.extend(self.tip);
+            return;
+        }
+
+        self.close_unmatched_blocks();
+        if self.blank {
+            if let Some(&last) = self.nodes[container].children.last() {
+                self.nodes[last].last_line_blank = true;
+            }
+        }
+        let node = &self.nodes[container];
+        // quote lines are never blank, blank lines in fenced code don't
+        // loosen lists, and neither does an empty list item just opened
+        let last_line_blank = self.blank
+            && !match &node.kind {
+                Kind::Quote | Kind::CodeBlock(Some(_)) | Kind::MathDisplay => true,
+                Kind::Item(_) => node.children.is_empty() && node.start_line == self.line_number,
+                _ => false,
+            };
+        let mut x = container;
+        loop {
+            self.nodes[x].last_line_blank = last_line_blank;
+            if x == 0 {
+                break;
+            }
+            x = self.nodes[x].parent;
+        }
+
+        match self.nodes[container].kind.clone() {
+            Kind::Table(_) => {
+                if !self.blank && self.offset < self.line.len() {
+                    let mut row = Content::default();
+                    row.push(&self.line[self.offset..], self.line_start + self.offset);
+                    self.nodes[container].rows.push(row);
+                }
+            }
+            Kind::HtmlBlock(kind) => {
+                let rest = &self.line[self.offset..];
+                self.add_line();
+                if (1..=5).contains(&kind) && html_block_end(rest, kind) {
+                    self.extend(container);
+                    self.finalize(container);
+                    return;
+                }
+            }
+            kind if kind.accepts_lines() => self.add_line(),
+            _ => {
+                if self.offset < self.line.len() && !self.blank {
+                    self.add_child(Kind::Paragraph, self.next_nonspace);
+                    self.advance_next_nonspace();
+                    self.add_line();
+                }
+            }
+        }
+        if !self.blank || matches!(self.nodes[self.tip].kind, Kind::CodeBlock(_) | Kind::HtmlBlock(_) | Kind::MathDisplay) {
+            self.extend(self.tip);
+        }
+    }
+
+    /// Tries to continue open block `container` on the current line.
+    fn continues(&mut self, container: usize) -> Continue {
+        match self.nodes[container].kind.clone() {
+            Kind::Document | Kind::List(_) => Continue::Matched,
+            Kind::Quote => {
+                if !self.indented && self.peek(self.next_nonspace) == Some(b'>') {
+                    self.advance_next_nonspace();
+                    self.advance_offset(1, false);
+                    if is_space_or_tab(self.peek(self.offset)) {
+                        self.advance_offset(1, true);
+                    }
+                    Continue::Matched
+                } else {
+                    Continue::Failed
+                }
+            }
+            Kind::Item(data) => {
+                if self.blank {
+                    if self.nodes[container].children.is_empty() {
+                        // an empty item ends at the first blank line
+                        return Continue::Failed;
+                    }
+                    self.advance_next_nonspace();
+                } else if self.indent >= data.marker_offset + data.padding {
+                    self.advance_offset(data.marker_offset + data.padding, true);
+                } else {
+                    return Continue::Failed;
+                }
+                Continue::Matched
+            }
+            Kind::Footnote(_) => {
+                if self.indent >= CODE_INDENT {
+                    self.advance_offset(CODE_INDENT, true);
+                } else if self.blank {
+                    self.advance_next_nonspace();
+                } else {
+                    return Continue::Failed;
+                }
+                Continue::Matched
+            }
+            Kind::Heading(..) | Kind::ThematicBreak(_) => Continue::Failed,
+            Kind::CodeBlock(Some(fence)) => {
+                let rest = &self.line.as_bytes()[self.next_nonspace..];
+                if self.indent <= 3 && closing_fence(rest, fence.ch) >= fence.len {
+                    self.finalize(container);
+                    return Continue::Consumed;
+                }
+                let mut i = fence.offset;
+                while i > 0 && is_space_or_tab(self.peek(self.offset)) {
+                    self.advance_offset(1, true);
+                    i -= 1;
+                }
+                Continue::Matched
+            }
+            Kind::CodeBlock(None) => {
+                if self.indent >= CODE_INDENT {
+                    self.advance_offset(CODE_INDENT, true);
+                } else if self.blank {
+                    self.advance_next_nonspace();
+                } else {
+                    return Continue::Failed;
+                }
+                Continue::Matched
+            }
+            Kind::HtmlBlock(kind) => {
+                if self.blank && (kind == 6 || kind == 7) {
+                    Continue::Failed
+                } else {
+                    Continue::Matched
+                }
+            }
+            Kind::Paragraph => {
+                if self.blank {
+                    Continue::Failed
+                } else {
+                    Continue::Matched
+                }
+            }
+            Kind::Table(alignments) => {
+                let missing = alignments.len().saturating_sub(split_row(&self.line[self.offset..]).len());
+                let node = &mut self.nodes[container];
+                if self.blank || node.autocompleted + missing > MAX_AUTOCOMPLETED_CELLS {
+                    Continue::Failed
+                } else {
+                    node.autocompleted += missing;
+                    Continue::Matched
+                }
+            }
+            Kind::MathDisplay => match self.line[self.offset..].find("$$") {
+                Some(at) => {
+                    let end = self.offset + at;
+                    if !self.line[self.offset..end].trim().is_empty() {
+                        let source = self.line_start + self.offset;
+                        let content = &mut self.nodes[container].content;
+                        content.push(&self.line[self.offset..end], source);
+                        content.text.push('\n');
+                    }
+                    self.finalize(container);
+                    Continue::Consumed
+                }
+                None => Continue::Matched,
+            },
+        }
+    }
+
+    /// Tries every kind of block that could start at the current position.
+    fn block_start(&mut self, container: usize) -> Start {
+        let rest = &self.line.as_bytes()[self.next_nonspace..];
+        let first = rest.first().copied();
+        let in_paragraph = self.nodes[container].kind == Kind::Paragraph;
+        let nestable = self.nodes[container].depth < MAX_NESTING;
+
+        // block quote
+        if nestable && !self.indented && first == Some(b'>') {
+            self.advance_next_nonspace();
+            self.advance_offset(1, false);
+            if is_space_or_tab(self.peek(self.offset)) {
+                self.advance_offset(1, true);
+            }
+            self.close_unmatched_blocks();
+            self.add_child(Kind::Quote, self.next_nonspace);
+            return Start::Container;
+        }
+
+        // footnote definition
+        if self.options.footnotes && nestable && !self.indented && !in_paragraph && rest.starts_with(b"[^") {
+            if let Some(len) = footnote_label(&rest[2..]) {
+                let label = self.line[self.next_nonspace + 2..self.next_nonspace + 2 + len].to_string();
+                self.close_unmatched_blocks();
+                self.add_child(Kind::Footnote(label), self.next_nonspace);
+                self.advance_next_nonspace();
+                self.advance_offset(len + 4, false);
+                self.find_next_nonspace();
+                self.advance_next_nonspace();
+                return Start::Container;
+            }
+        }
+
+        // ATX heading
+        if !self.indented && first == Some(b'#') {
+            let level = rest.iter().take_while(|&&x| x == b'#').count();
+            if level <= 6 && matches!(rest.get(level), None | Some(b' ') | Some(b'\t')) {
+                self.advance_next_nonspace();
+                self.advance_offset(level, false);
+                self.close_unmatched_blocks();
+                let id = self.add_child(Kind::Heading(level as u8, HeadingStyle::Atx), self.next_nonspace);
+                let bytes = self.line.as_bytes();
+                let mut start = self.offset;
+                while is_space_or_tab(bytes.get(start).copied()) {
+                    start += 1;
+                }
+                let mut end = self.line[start..].trim_end_matches([' ', '\t']).len() + start;
+                let hashes = bytes[start..end].iter().rev().take_while(|&&x| x == b'#').count();
+                if hashes == end - start {
+                    end = start;
+                } else if hashes > 0 && is_space_or_tab(Some(bytes[end - hashes - 1])) {
+                    end = self.line[start..end - hashes].trim_end_matches([' ', '\t']).len() + start;
+                }
+                let source = self.line_start + start;
+                self.nodes[id].content.push(&self.line[start..end], source);
+                self.advance_to_end();
+                return Start::Leaf;
+            }
+        }
+
+        // fenced code block
+        if !self.indented && (first == Some(b'`') || first == Some(b'~')) {
+            let ch = first.unwrap();
+            let len = rest.iter().take_while(|&&x| x == ch).count();
+            if len >= 3 && !(ch == b'`' && rest[len..].contains(&b'`')) {
+                self.close_unmatched_blocks();
+                let fence = Fence { ch, len, offset: self.indent };
+                self.add_child(Kind::CodeBlock(Some(fence)), self.next_nonspace);
+                self.advance_next_nonspace();
+                self.advance_offset(len, false);
+                return Start::Leaf;
+            }
+        }
+
+        // display math
+        if self.options.math && !self.indented && rest.starts_with(b"$$") {
+            self.close_unmatched_blocks();
+            let id = self.add_child(Kind::MathDisplay, self.next_nonspace);
+            self.advance_next_nonspace();
+            self.advance_offset(2, false);
+            if let Some(at) = self.line[self.offset..].find("$$") {
+                // opened and closed on the same line
+                let end = self.offset + at;
+                let source = self.line_start + self.offset;
+                self.nodes[id].content.push(&self.line[self.offset..end], source);
+                self.advance_to_end();
+                self.extend(id);
+                self.finalize(id);
+            }
+            return Start::Leaf;
+        }
+
+        // HTML block
+        if !self.indented && first == Some(b'<') {
+            let lazy = !self.all_closed && !self.blank && self.nodes[self.tip].kind == Kind::Paragraph;
+            for kind in 1..=7 {
+                if html_block_start(rest, kind) && (kind < 7 || (!in_paragraph && !lazy)) {
+                    self.close_unmatched_blocks();
+                    // the indentation is part of the block
+                    self.add_child(Kind::HtmlBlock(kind), self.offset);
+                    return Start::Leaf;
+                }
+            }
+        }
+
+        // setext heading
+        if !self.indented && in_paragraph {
+            if let Some(level) = setext_level(rest) {
+                self.close_unmatched_blocks();
+                self.extract_references(container);
+                if !self.nodes[container].content.text.is_empty() {
+                    self.nodes[container].kind = Kind::Heading(level, HeadingStyle::Setext);
+                    self.advance_to_end();
+                    return Start::Leaf;
+                }
+                // the paragraph held only definitions, so the line starts
+                // afresh, as a break, a list item or a new paragraph
+                self.remove_empty_paragraph(container);
+                return Start::Container;
+            }
+        }
+
+        // table
+        if self.options.tables && !self.indented && in_paragraph {
+            if let Some(start) = self.table_start(container) {
+                return start;
+            }
+        }
+
+        // thematic break
+        if !self.indented && is_thematic_break(rest) {
+            self.close_unmatched_blocks();
+            self.add_child(Kind::ThematicBreak(rest[0]), self.next_nonspace);
+            self.advance_to_end();
+            return Start::Leaf;
+        }
+
+        // list item
+        let in_list = matches!(self.nodes[container].kind, Kind::List(_));
+        if nestable && (!self.indented || in_list) {
+            if let Some(data) = self.list_marker(in_paragraph) {
+                self.close_unmatched_blocks();
+                let continues_list = match self.nodes[self.tip].kind {
+                    Kind::List(list) => list.ordered == data.ordered && list.marker == data.marker,
+                    _ => false,
+                };
+                if !continues_list {
+                    self.add_child(Kind::List(data), self.next_nonspace);
+                }
+                self.add_child(Kind::Item(data), self.next_nonspace);
+                return Start::Container;
+            }
+        }
+
+        // indented code block
+        if self.indented && self.nodes[self.tip].kind != Kind::Paragraph && !self.blank {
+            self.advance_offset(CODE_INDENT, true);
+            self.close_unmatched_blocks();
+            self.add_child(Kind::CodeBlock(None), self.offset);
+            return Start::Leaf;
+        }
+
+        Start::None
+    }
+
+    /// Turns the last line of paragraph `container` into the header of a
+    /// table if the current line is a matching delimiter row.
+    fn table_start(&mut self, container: usize) -> Option<Start> {
+        let delimiter = &self.line[self.next_nonspace..];
+        let alignments = delimiter_row(delimiter)?;
+        let text = self.nodes[container].content.text.strip_suffix('\n')?;
+        let header_start = text.rfind('\n').map_or(0, |x| x + 1);
+        let header = text[header_start..].to_string();
+        if split_row(&header).len() != alignments.len() || !(header.contains('|') || delimiter.contains('|')) {
+            return None;
+        }
+
+        self.close_unmatched_blocks();
+        let mut row = Content::default();
+        let source = self.nodes[container].content.source(header_start);
+        row.push(&header, source);
+        if header_start == 0 {
+            let node = &mut self.nodes[container];
+            node.kind = Kind::Table(alignments);
+            node.content = Content::default();
+            node.rows.push(row);
+        } else {
+            self.nodes[container].content.text.truncate(header_start);
+            self.finalize(container);
+            let id = self.add_child(Kind::Table(alignments), 0);
+            self.nodes[id].start = source;
+            self.nodes[id].rows.push(row);
+        }
+        self.advance_to_end();
+        Some(Start::Leaf)
+    }
+
+    /// Reads a list marker at the next non-space character, moving past it
+    /// and the spaces after it.
+    fn list_marker(&mut self, in_paragraph: bool) -> Option<ListData> {
+        if self.indent >= 4 {
+            return None;
+        }
+        let rest = &self.line.as_bytes()[self.next_nonspace..];
+        let mut data = ListData { ordered: false, marker: 0, start: 1, marker_offset: self.indent, padding: 0 };
+        let marker_len;
+        match rest.first() {
+            Some(&c) if c == b'*' || c == b'+' || c == b'-' || (c == b'=' && self.options.equals_bullet) => {
+                data.marker = c;
+                marker_len = 1;
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let digits = rest.iter().take_while(|x| x.is_ascii_digit()).count();
+                let delimiter = rest.get(digits).copied();
+                if digits > 9 || !(delimiter == Some(b'.') || delimiter == Some(b')')) {
+                    return None;
+                }
+                let start: u32 = std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+                // only a list starting at 1 may interrupt a paragraph
+                if in_paragraph && start != 1 {
+                    return None;
+                }
+                data.ordered = true;
+                data.marker = delimiter.unwrap();
+                data.start = start;
+                marker_len = digits + 1;
+            }
+            _ => return None,
+        }
+        let next = rest.get(marker_len).copied();
+        if !(next.is_none() || is_space_or_tab(next)) {
+            return None;
+        }
+        // an empty item cannot interrupt a paragraph
+        if in_paragraph && rest[marker_len..].iter().all(|&x| x == b' ' || x == b'\t') {
+            return None;
+        }
+
+        self.advance_next_nonspace();
+        self.advance_offset(marker_len, true);
+        let spaces_start_column = self.column;
+        let spaces_start_offset = self.offset;
+        loop {
+            self.advance_offset(1, true);
+            if !(self.column - spaces_start_column < 5 && is_space_or_tab(self.peek(self.offset))) {
+                break;
+            }
+        }
+        let blank_item = self.peek(self.offset).is_none();
+        let spaces_after_marker = self.column - spaces_start_column;
+        if !(1..5).contains(&spaces_after_marker) || blank_item {
+            // the content starts one space after the marker, the rest of
+            // the spaces belong to it
+            data.padding = marker_len + 1;
+            self.column = spaces_start_column;
+            self.offset = spaces_start_offset;
+            self.partially_consumed_tab = false;
+            if is_space_or_tab(self.peek(self.offset)) {
+                self.advance_offset(1, true);
+            }
+        } else {
+            data.padding = marker_len + spaces_after_marker;
+        }
+        Some(data)
+    }
+
+    /// Removes the link reference definitions at the start of a paragraph.
+    fn extract_references(&mut self, paragraph: usize) {
+        let content = &mut self.nodes[paragraph].content;
+        let mut pos = 0;
+        while content.text[pos..].starts_with('[') {
+            let n = inline::parse_reference(&content.text[pos..], &mut self.refmap);
+            if n == 0 {
+                break;
+            }
+            pos += n;
+        }
+        // drop them all at once, advancing per definition is quadratic
+        if pos > 0 {
+            content.advance(pos);
+        }
+    }
+
+    /// Drops `paragraph`, the tip, after its definitions left it empty.
+    fn remove_empty_paragraph(&mut self, paragraph: usize) {
+        let parent = self.nodes[paragraph].parent;
+        self.nodes[paragraph].open = false;
+        self.nodes[parent].children.pop();
+        self.tip = parent;
+    }
+
+    fn ends_with_blank_line(&mut self, mut block: usize) -> bool {
+        loop {
+            if self.nodes[block].last_line_blank {
+                return true;
+            }
+            let node = &mut self.nodes[block];
+            let descend = matches!(node.kind, Kind::List(_) | Kind::Item(_)) && !node.last_line_checked;
+            node.last_line_checked = true;
+            match node.children.last() {
+                Some(&last) if descend => block = last,
+                _ => return false,
+            }
+        }
+    }
+
+    fn finalize(&mut self, block: usize) {
+        let parent = self.nodes[block].parent;
+        self.nodes[block].open = false;
+        match self.nodes[block].kind.clone() {
+            Kind::Paragraph => self.extract_references(block),
+            Kind::CodeBlock(Some(_)) => {
+                let content = &mut self.nodes[block].content;
+                let first_line = content.text.find('\n').unwrap_or(content.text.len());
+                let info = inline::unescape(content.text[..first_line].trim());
+                let skip = (first_line + 1).min(content.text.len());
+                content.advance(skip);
+                self.nodes[block].info = info;
+            }
+            Kind::CodeBlock(None) => strip_blank_lines(&mut self.nodes[block].content.text, true),
+            Kind::HtmlBlock(_) => strip_blank_lines(&mut self.nodes[block].content.text, false),
+            Kind::MathDisplay => {
+                let content = &mut self.nodes[block].content;
+                if content.text.starts_with('\n') {
+                    content.advance(1);
+                }
+                let len = content.text.trim_end().len();
+                content.text.truncate(len);
+            }
+            Kind::List(_) => {
+                let items = self.nodes[block].children.clone();
+                let mut tight = true;
+                'items: for (i, &item) in items.iter().enumerate() {
+                    let has_next = i + 1 < items.len();
+                    if self.ends_with_blank_line(item) && has_next {
+                        tight = false;
+                        break;
+                    }
+                    let subs = self.nodes[item].children.clone();
+                    for (j, &sub) in subs.iter().enumerate() {
+                        if self.ends_with_blank_line(sub) && (has_next || j + 1 < subs.len()) {
+                            tight = false;
+                            break 'items;
+                        }
+                    }
+                }
+                self.nodes[block].tight = tight;
+            }
+            _ => {}
+        }
+        self.tip = parent;
+    }
+}
+
+impl Tree {
+    pub(super) fn document(&self, input: &str, options: &Options, index: &LineIndex) -> Document {
+        let mut footnotes = Footnotes::default();
+        for (id, node) in self.nodes.iter().enumerate() {
+            if let Kind::Footnote(label) = &node.kind {
+                footnotes.defined.entry(inline::normalize_label(label)).or_insert(id);
+            }
+        }
+        let mut builder = Builder { tree: self, options, index, footnotes };
+        let blocks = builder.blocks(0);
+        // the definitions may reference further footnotes
+        let mut number = 1;
+        while number <= builder.footnotes.notes.len() {
+            let note = &builder.footnotes.notes[number - 1];
+            if let Some(&id) = builder.footnotes.defined.get(&note.label) {
+                let blocks = builder.blocks(id);
+                let node = &self.nodes[id];
+                let span = builder.span(node);
+                let note = &mut builder.footnotes.notes[number - 1];
+                if let Kind::Footnote(label) = &node.kind {
+                    note.label = label.clone();
+                }
+                note.blocks = blocks;
+                note.span = span;
+            }
+            number += 1;
+        }
+        Document {
+            blocks,
+            footnotes: builder.footnotes.notes,
+            span: Span { start: index.position(0), end: index.position(input.len()) },
+        }
+    }
+}
+
+/// Turns the block tree into the AST, parsing inline content on the way.
+struct Builder<'a> {
+    tree: &'a Tree,
+    options: &'a Options,
+    index: &'a LineIndex,
+    footnotes: Footnotes,
+}
+
+impl<'a> Builder<'a> {
+    fn span(&self, node: &Node) -> Span {
+        Span { start: self.index.position(node.start), end: self.index.position(node.end.max(node.start)) }
+    }
+
+    /// Inline content of the whole of `content`, without surrounding
+    /// whitespace.
+    fn text(&mut self, content: &Content) -> Text {
+        let text = &content.text;
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len().max(start);
+        inline::parse(content, start, end, &self.tree.refmap, &mut self.footnotes, self.options, self.index)
+    }
+
+    fn blocks(&mut self, id: usize) -> Vec<Block> {
+        let tree = self.tree;
+        tree.nodes[id].children.iter().filter_map(|&x| self.block(x)).collect()
+    }
+
+    fn block(&mut self, id: usize) -> Option<Block> {
+        let tree = self.tree;
+        let node = &tree.nodes[id];
+        let span = self.span(node);
+        Some(match &node.kind {
+            Kind::Paragraph => {
+                if node.content.text.trim().is_empty() {
+                    return None;
+                }
+                Block::PlainParagraph(PlainParagraph { text: self.text(&node.content), span })
+            }
+            Kind::Heading(rank, style) => {
+                Block::Heading(Heading { rank: *rank, style: *style, text: self.text(&node.content), span })
+            }
+            Kind::ThematicBreak(marker) => Block::ThematicBreak(ThematicBreak { marker: *marker as char, span }),
+            Kind::CodeBlock(fence) => {
+                let kind = if fence.is_some() { CodeBlockKind::Fenced } else { CodeBlockKind::Indented };
+                Block::CodeBlock(CodeBlock { kind, text: node.content.text.clone(), span, ..parse_info(&node.info) })
+            }
+            Kind::HtmlBlock(_) => Block::HtmlBlock(HtmlBlock { html: node.content.text.clone(), span }),
+            Kind::MathDisplay => Block::MathDisplay(MathDisplay { formula: node.content.text.clone(), span }),
+            Kind::Quote => Block::Quote(Quote { blocks: self.blocks(id), span }),
+            Kind::List(data) => {
+                let items = node.children.iter().map(|&x| self.item(x)).collect();
+                if data.ordered {
+                    Block::OrderedList(OrderedList {
+                        start: data.start,
+                        delimiter: data.marker as char,
+                        tight: node.tight,
+                        items,
+                        span,
+                    })
+                } else {
+                    Block::UnorderedList(UnorderedList { bullet: data.marker as char, tight: node.tight, items, span })
+                }
+            }
+            Kind::Table(alignments) => {
+                let mut rows = node.rows.iter().map(|x| self.row(x, alignments.len()));
+                let header = rows.next().unwrap_or_default();
+                Block::Table(Table { alignments: alignments.clone(), header, rows: rows.collect(), span })
+            }
+            Kind::Item(_) | Kind::Document | Kind::Footnote(_) => return None,
+        })
+    }
+
+    fn item(&mut self, id: usize) -> ListItem {
+        let tree = self.tree;
+        let node = &tree.nodes[id];
+        let mut item = ListItem { checked: None, blocks: vec![], span: self.span(node) };
+        let mut children = node.children.iter();
+        if self.options.task_lists {
+            if let Some(&first) = node.children.first() {
+                if let Some((checked, paragraph)) = self.task(first) {
+                    item.checked = Some(checked);
+                    item.blocks.push(paragraph);
+                    children.next();
+                }
+            }
+        }
+        item.blocks.extend(children.filter_map(|&x| self.block(x)));
+        item
+    }
+
+    /// A paragraph starting with `[ ]` or `[x]` and the rest of it.
+    fn task(&mut self, id: usize) -> Option<(bool, Block)> {
+        let node = &self.tree.nodes[id];
+        if node.kind != Kind::Paragraph {
+            return None;
+        }
+        let b = node.content.text.as_bytes();
+        if b.len() < 4 || b[0] != b'[' || b[2] != b']' || !matches!(b[3], b' ' | b'\t' | b'\n') {
+            return None;
+        }
+        let checked = match b[1] {
+            b' ' => false,
+            b'x' | b'X' => true,
+            _ => return None,
+        };
+        let mut content = node.content.clone();
+        content.advance(3);
+        if content.text.trim().is_empty() {
+            return None;
+        }
+        let paragraph = PlainParagraph { text: self.text(&content), span: self.span(node) };
+        Some((checked, Block::PlainParagraph(paragraph)))
+    }
+
+    fn row(&mut self, row: &Content, columns: usize) -> TableRow {
+        let mut cells: Vec<Text> = split_row(&row.text)
+            .into_iter()
+            .take(columns)
+            .map(|(start, end)| {
+                // `\|` is a pipe even inside code spans, so drop the backslash
+                // by starting a new piece at the pipe
+                let text = &row.text[start..end];
+                let mut cell = Content::default();
+                let mut last = 0;
+                for (i, _) in text.match_indices("\\|") {
+                    if i > last {
+                        cell.push(&text[last..i], row.source(start + last));
+                    }
+                    last = i + 1;
+                }
+                cell.push(&text[last..], row.source(start + last));
+                self.text(&cell)
+            })
+            .collect();
+        cells.resize_with(columns, Text::default);
+        let end = row.text.len();
+        TableRow { cells, span: row.span(0, end, self.index) }
+    }
+}
@@ -0,0 +1,1427 @@
+//! The inline phase.
+//!
+//! Text is parsed into a tree of nodes linked to their siblings, while runs
+//! of `*`, `_` and `~` are pushed on a delimiter stack and `[` and `![` on a
+//! bracket stack. A closing bracket turns everything after its opener into a
+//! link, and the delimiters are then matched up into emphasis, first inside
+//! every link and finally over the whole text. Inline `^[...]` footnotes
+//! are brackets too.
+
+use std::collections::HashMap;
+
+use super::{Content, MAX_NESTING};
+use crate::ast::*;
+use crate::entities;
+use crate::options::Options;
+use crate::position::LineIndex;
+
+/// The url and title of a link reference definition.
+#[derive(Debug, Clone)]
+pub(super) struct LinkDef {
+    url: String,
+    title: String,
+}
+
+/// Link reference definitions by normalized label.
+pub(super) type RefMap = HashMap<String, LinkDef>;
+
+/// The footnotes of a document, numbered as references to them are
+/// converted, which happens in document order.
+#[derive(Debug, Default)]
+pub(super) struct Footnotes {
+    /// the block of every footnote definition, by normalized label
+    pub(super) defined: HashMap<String, usize>,
+    /// the number of every definition referenced so far
+    numbers: HashMap<String, usize>,
+    /// the footnotes referenced so far, by number from 1
+    pub(super) notes: Vec<Footnote>,
+}
+
+impl Footnotes {
+    /// Adds a footnote, returning its number.
+    fn add(&mut self, label: &str) -> usize {
+        let number = self.notes.len() + 1;
+        self.notes.push(Footnote { number, label: label.to_string(), ..Footnote::default() });
+        number
+    }
+
+    /// Counts a reference to the definition of normalized `label`, returning
+    /// the number of the footnote and of the reference.
+    fn reference(&mut self, label: &str) -> (usize, usize) {
+        let number = match self.numbers.get(label) {
+            Some(&number) => number,
+            None => {
+                let number = self.add(label);
+                self.numbers.insert(label.to_string(), number);
+                number
+            }
+        };
+        let note = &mut self.notes[number - 1];
+        note.references += 1;
+        (number, note.references)
+    }
+}
+
+const MAX_LABEL: usize = 999;
+/// How deeply parentheses may nest in a link destination, as in cmark.
+const MAX_LINK_PARENS: usize = 32;
+
+fn is_space_or_tab(c: Option<u8>) -> bool {
+    c == Some(b' ') || c == Some(b'\t')
+}
+
+/// Unicode punctuation, approximated by ASCII punctuation and the general
+/// punctuation blocks.
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(c, '\u{a1}' | '\u{a7}' | '\u{ab}' | '\u{b6}' | '\u{b7}' | '\u{bb}' | '\u{bf}')
+        || matches!(c, '\u{2010}'..='\u{2027}' | '\u{2030}'..='\u{205e}' | '\u{3001}'..='\u{3003}')
+        || matches!(c, '\u{3008}'..='\u{3011}' | '\u{ff01}'..='\u{ff0f}' | '\u{ff1a}'..='\u{ff20}')
+}
+
+/// The case-folded label with its whitespace collapsed, under which a
+/// reference is looked up.
+pub(super) fn normalize_label(label: &str) -> String {
+    let collapsed = label.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.to_lowercase().to_uppercase()
+}
+
+/// The character reference at the start of `s` and its length.
+fn entity(s: &str) -> Option<(String, usize)> {
+    let b = s.as_bytes();
+    if b.get(1) == Some(&b'#') {
+        let hex = matches!(b.get(2), Some(b'x') | Some(b'X'));
+        let start = if hex { 3 } else { 2 };
+        let (radix, max) = if hex { (16, 6) } else { (10, 7) };
+        let digits = b[start..].iter().take_while(|x| x.is_ascii_hexdigit() && (hex || x.is_ascii_digit())).count();
+        if digits == 0 || digits > max || b.get(start + digits) != Some(&b';') {
+            return None;
+        }
+        let value = u32::from_str_radix(&s[start..start + digits], radix).ok()?;
+        let c = match value {
+            0 => '\u{fffd}',
+            _ => std::char::from_u32(value).unwrap_or('\u{fffd}'),
+        };
+        return Some((c.to_string(), start + digits + 1));
+    }
+    let name_len = b[1..].iter().take_while(|x| x.is_ascii_alphanumeric()).count();
+    if name_len == 0 || name_len > 32 || b.get(1 + name_len) != Some(&b';') {
+        return None;
+    }
+    let value = entities::lookup(&s[1..1 + name_len])?;
+    Some((value.to_string(), name_len + 2))
+}
+
+/// Resolves backslash escapes and character references.
+pub(super) fn unescape(s: &str) -> String {
+    resolve(s, true)
+}
+
+/// Resolves the character references of text that is already HTML, such as
+/// the attribute values of raw HTML tags.
+pub(crate) fn decode_references(s: &str) -> String {
+    resolve(s, false)
+}
+
+fn resolve(s: &str, backslash_escapes: bool) -> String {
+    let b = s.as_bytes();
+    let mut out = String::new();
+    let mut last = 0;
+    let mut i = 0;
+    while i < b.len() {
+        match b[i] {
+            b'\\' if backslash_escapes && b.get(i + 1).is_some_and(|x| x.is_ascii_punctuation()) => {
+                out.push_str(&s[last..i]);
+                last = i + 1;
+                i += 2;
+            }
+            b'&' => match entity(&s[i..]) {
+                Some((value, len)) => {
+                    out.push_str(&s[last..i]);
+                    out.push_str(&value);
+                    i += len;
+                    last = i;
+                }
+                None => i += 1,
+            },
+            _ => i += 1,
+        }
+    }
+    out.push_str(&s[last..]);
+    out
+}
+
+/// Skips spaces, tabs and at most one line ending.
+fn spnl(s: &[u8], mut pos: usize) -> usize {
+    while is_space_or_tab(s.get(pos).copied()) {
+        pos += 1;
+    }
+    if s.get(pos) == Some(&b'\n') {
+        pos += 1;
+        while is_space_or_tab(s.get(pos).copied()) {
+            pos += 1;
+        }
+    }
+    pos
+}
+
+/// The length of the link label `[...]` at `pos`, or 0.
+fn link_label(s: &[u8], pos: usize) -> usize {
+    if s.get(pos) != Some(&b'[') {
+        return 0;
+    }
+    let mut p = pos + 1;
+    while p < s.len() && p - pos <= MAX_LABEL + 1 {
+        match s[p] {
+            b'\\' if p + 1 < s.len() => p += 2,
+            b'[' => return 0,
+            b']' => return p + 1 - pos,
+            _ => p += 1,
+        }
+    }
+    0
+}
+
+/// The link destination at `pos`, unescaped, and where it ends.
+fn link_destination(s: &str, pos: usize) -> Option<(String, usize)> {
+    let b = s.as_bytes();
+    if b.get(pos) == Some(&b'<') {
+        let mut p = pos + 1;
+        while p < b.len() {
+            match b[p] {
+                b'\\' if p + 1 < b.len() => p += 2,
+                b'>' => return Some((unescape(&s[pos + 1..p]), p + 1)),
+                b'<' | b'\n' => return None,
+                _ => p += 1,
+            }
+        }
+        return None;
+    }
+    let mut p = pos;
+    let mut parens = 0;
+    while p < b.len() {
+        match b[p] {
+            b'\\' if b.get(p + 1).is_some_and(|x| x.is_ascii_punctuation()) => p += 2,
+            b'(' if parens == MAX_LINK_PARENS => return None,
+            b'(' => {
+                parens += 1;
+                p += 1;
+            }
+            b')' if parens == 0 => break,
+            b')' => {
+                parens -= 1;
+                p += 1;
+            }
+            c if c == b' ' || c.is_ascii_control() => break,
+            _ => p += 1,
+        }
+    }
+    if (p == pos && b.get(p) != Some(&b')')) || parens != 0 {
+        return None;
+    }
+    Some((unescape(&s[pos..p]), p))
+}
+
+/// The link title at `pos`, unescaped, and where it ends.
+fn link_title(s: &str, pos: usize) -> Option<(String, usize)> {
+    let b = s.as_bytes();
+    let close = match b.get(pos)? {
+        b'"' => b'"',
+        b'\'' => b'\'',
+        b'(' => b')',
+        _ => return None,
+    };
+    let mut p = pos + 1;
+    while p < b.len() {
+        match b[p] {
+            b'\\' if p + 1 < b.len() => p += 2,
+            c if c == close => return Some((unescape(&s[pos + 1..p]), p + 1)),
+            b'(' if close == b')' => return None,
+            _ => p += 1,
+        }
+    }
+    None
+}
+
+/// Reads the link reference definition at the start of `s` into `refmap`,
+/// returning its length or 0 if there is none.
+pub(super) fn parse_reference(s: &str, refmap: &mut RefMap) -> usize {
+    let b = s.as_bytes();
+    let label_len = link_label(b, 0);
+    if label_len == 0 || b.get(label_len) != Some(&b':') {
+        return 0;
+    }
+    let label = normalize_label(&s[1..label_len - 1]);
+    if label.is_empty() {
+        return 0;
+    }
+    let pos = spnl(b, label_len + 1);
+    let (url, after_url) = match link_destination(s, pos) {
+        Some(x) => x,
+        None => return 0,
+    };
+    if after_url == pos {
+        return 0;
+    }
+
+    let at_line_end = |mut p: usize| {
+        while is_space_or_tab(b.get(p).copied()) {
+            p += 1;
+        }
+        match b.get(p) {
+            None => Some(p),
+            Some(b'\n') => Some(p + 1),
+            _ => None,
+        }
+    };
+    let title_pos = spnl(b, after_url);
+    let titled = if title_pos > after_url { link_title(s, title_pos) } else { None };
+    let (title, end) = match titled.and_then(|(title, p)| Some((title, at_line_end(p)?))) {
+        Some(x) => x,
+        // without a title the definition has to end with the destination
+        None => match at_line_end(after_url) {
+            Some(end) => (String::new(), end),
+            None => return 0,
+        },
+    };
+    refmap.entry(label).or_insert(LinkDef { url, title });
+    end
+}
+
+fn tag_name(s: &[u8], mut pos: usize) -> Option<usize> {
+    if !s.get(pos)?.is_ascii_alphabetic() {
+        return None;
+    }
+    while s.get(pos).is_some_and(|x| x.is_ascii_alphanumeric() || *x == b'-') {
+        pos += 1;
+    }
+    Some(pos)
+}
+
+fn skip_whitespace(s: &[u8], mut pos: usize) -> usize {
+    while s.get(pos).is_some_and(|x| x.is_ascii_whitespace()) {
+        pos += 1;
+    }
+    pos
+}
+
+/// The end of the open tag starting at the `<` at `pos`.
+pub(super) fn open_tag(s: &[u8], pos: usize) -> Option<usize> {
+    let mut p = tag_name(s, pos + 1)?;
+    loop {
+        let after_space = skip_whitespace(s, p);
+        match s.get(after_space)? {
+            b'>' => return Some(after_space + 1),
+            b'/' => return if s.get(after_space + 1) == Some(&b'>') { Some(after_space + 2) } else { None },
+            &c if after_space > p && (c.is_ascii_alphabetic() || c == b'_' || c == b':') => {
+                p = after_space + 1;
+                while s.get(p).is_some_and(|x| x.is_ascii_alphanumeric() || b"_.:-".contains(x)) {
+                    p += 1;
+                }
+                let eq = skip_whitespace(s, p);
+                if s.get(eq) != Some(&b'=') {
+                    continue;
+                }
+                let value = skip_whitespace(s, eq + 1);
+                p = match s.get(value)? {
+                    &quote @ b'"' | &quote @ b'\'' => {
+                        value + 2 + s[value + 1..].iter().position(|&x| x == quote)?
+                    }
+                    _ => {
+                        let len = s[value..]
+                            .iter()
+                            .take_while(|&&x| x > b' ' && !b"\"'=<>`".contains(&x))
+                            .count();
+                        if len == 0 {
+                            return None;
+                        }
+                        value + len
+                    }
+                };
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// The end of the closing tag starting at the `<` at `pos`.
+pub(super) fn closing_tag(s: &[u8], pos: usize) -> Option<usize> {
+    if s.get(pos + 1) != Some(&b'/') {
+        return None;
+    }
+    let p = skip_whitespace(s, tag_name(s, pos + 2)?);
+    if s.get(p) == Some(&b'>') {
+        Some(p + 1)
+    } else {
+        None
+    }
+}
+
+fn find(s: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    s.get(from..)?.windows(needle.len()).position(|x| x == needle).map(|x| x + from)
+}
+
+/// The end of the raw HTML starting at the `<` at `pos`.
+fn html_inline(s: &[u8], pos: usize) -> Option<usize> {
+    let rest = &s[pos..];
+    if rest.starts_with(b"<!--") {
+        let text = pos + 4;
+        if s[text..].starts_with(b">") || s[text..].starts_with(b"->") {
+            return None;
+        }
+        let end = find(s, text, b"--")?;
+        return if s[end..].starts_with(b"-->") { Some(end + 3) } else { None };
+    }
+    if rest.starts_with(b"<?") {
+        return find(s, pos + 2, b"?>").map(|x| x + 2);
+    }
+    if rest.starts_with(b"<![CDATA[") {
+        return find(s, pos + 9, b"]]>").map(|x| x + 3);
+    }
+    if rest.starts_with(b"<!") {
+        let name = rest[2..].iter().take_while(|x| x.is_ascii_uppercase()).count();
+        if name == 0 || !rest.get(2 + name).is_some_and(|x| x.is_ascii_whitespace()) {
+            return None;
+        }
+        return find(s, pos + 2 + name, b">").map(|x| x + 1);
+    }
+    open_tag(s, pos).or_else(|| closing_tag(s, pos))
+}
+
+/// The end of the autolink `<scheme:...>` or `<email>` at `pos`, and whether
+/// it is an email address.
+fn autolink(s: &[u8], pos: usize) -> Option<(usize, bool)> {
+    let rest = &s[pos + 1..];
+    let scheme = rest.iter().take_while(|x| x.is_ascii_alphanumeric() || b"+.-".contains(x)).count();
+    if (2..=32).contains(&scheme) && rest[0].is_ascii_alphabetic() && rest.get(scheme) == Some(&b':') {
+        let len = rest[scheme..].iter().take_while(|&&x| x > b' ' && x != b'<' && x != b'>').count();
+        if rest.get(scheme + len) == Some(&b'>') {
+            return Some((pos + 1 + scheme + len + 1, false));
+        }
+    }
+
+    let local = rest.iter().take_while(|x| x.is_ascii_alphanumeric() || b".!#$%&'*+/=?^_`{|}~-".contains(x)).count();
+    if local == 0 || rest.get(local) != Some(&b'@') {
+        return None;
+    }
+    let mut p = local + 1;
+    loop {
+        let label = rest[p..].iter().take_while(|x| x.is_ascii_alphanumeric() || **x == b'-').count();
+        if label == 0 || label > 63 || rest[p] == b'-' || rest[p + label - 1] == b'-' {
+            return None;
+        }
+        p += label;
+        match rest.get(p) {
+            Some(b'.') => p += 1,
+            Some(b'>') => return Some((pos + 1 + p + 1, true)),
+            _ => return None,
+        }
+    }
+}
+
+/// Where an extended autolink ends, trimming trailing punctuation, an
+/// entity-like `&name;` and unbalanced closing parentheses.
+fn autolink_delim(s: &[u8], mut end: usize) -> usize {
+    if let Some(lt) = s[..end].iter().position(|&x| x == b'<') {
+        end = lt;
+    }
+    let opening = s[..end].iter().filter(|&&x| x == b'(').count();
+    let mut closing = s[..end].iter().filter(|&&x| x == b')').count();
+    while end > 0 {
+        let last = s[end - 1];
+        if b"?!.,:*_~'\"".contains(&last) {
+            end -= 1;
+        } else if last == b';' {
+            let mut p = end - 1;
+            while p > 0 && s[p - 1].is_ascii_alphabetic() {
+                p -= 1;
+            }
+            if p > 0 && p < end - 1 && s[p - 1] == b'&' {
+                end = p - 1;
+            } else {
+                end -= 1;
+            }
+        } else if last == b')' {
+            if closing <= opening {
+                break;
+            }
+            closing -= 1;
+            end -= 1;
+        } else {
+            break;
+        }
+    }
+    end
+}
+
+/// How much of `s` is a domain, rejecting underscores in its last two
+/// segments and, unless `allow_short`, domains without a dot.
+fn check_domain(s: &str, allow_short: bool) -> usize {
+    let mut end = s.len();
+    let mut dots = 0;
+    let (mut underscores1, mut underscores2) = (0, 0);
+    for (i, c) in s.char_indices().skip(1) {
+        if c == '_' {
+            underscores2 += 1;
+        } else if c == '.' {
+            underscores1 = underscores2;
+            underscores2 = 0;
+            dots += 1;
+        } else if c != '-' && (c.is_whitespace() || is_punctuation(c)) {
+            end = i;
+            break;
+        }
+    }
+    if underscores1 > 0 || underscores2 > 0 || (!allow_short && dots == 0) {
+        0
+    } else {
+        end
+    }
+}
+
+/// The extended autolink of `text` found at `at`, as its range and url.
+fn extended_autolink(text: &str, at: usize) -> Option<(usize, usize, String)> {
+    let b = text.as_bytes();
+    let extend = |mut end: usize| {
+        while end < b.len() && !b[end].is_ascii_whitespace() && b[end] != b'<' {
+            end += 1;
+        }
+        end
+    };
+    match b[at] {
+        b'w' => {
+            let boundary = at == 0 || b[at - 1].is_ascii_whitespace() || b"*_~(".contains(&b[at - 1]);
+            if !boundary || !text[at..].starts_with("www.") {
+                return None;
+            }
+            let domain = check_domain(&text[at..], false);
+            if domain == 0 {
+                return None;
+            }
+            let end = at + autolink_delim(&b[at..], extend(at + domain) - at);
+            Some((at, end, format!("http://{}", &text[at..end])))
+        }
+        b':' => {
+            if !text[at..].starts_with("://") {
+                return None;
+            }
+            let start = at - b[..at].iter().rev().take_while(|x| x.is_ascii_alphabetic()).count();
+            if !["http", "https", "ftp"].contains(&&text[start..at]) {
+                return None;
+            }
+            let domain = check_domain(&text[at + 3..], true);
+            if domain == 0 {
+                return None;
+            }
+            let end = start + autolink_delim(&b[start..], extend(at + 3 + domain) - start);
+            Some((start, end, text[start..end].to_string()))
+        }
+        b'@' => {
+            let start = at - b[..at].iter().rev().take_while(|x| x.is_ascii_alphanumeric() || b".+-_".contains(x)).count();
+            if start == at {
+                return None;
+            }
+            let mut end = at + 1;
+            let mut dots = 0;
+            while end < b.len() {
+                match b[end] {
+                    c if c.is_ascii_alphanumeric() => {}
+                    b'.' if b.get(end + 1).is_some_and(|x| x.is_ascii_alphanumeric()) => dots += 1,
+                    b'-' | b'_' => {}
+                    _ => break,
+                }
+                end += 1;
+            }
+            let last = b[end - 1];
+            if end - at < 2 || dots == 0 || !(last.is_ascii_alphabetic() || last == b'.') {
+                return None;
+            }
+            let end = at + autolink_delim(&b[at..], end - at);
+            if end <= at + 1 {
+                return None;
+            }
+            Some((start, end, format!("mailto:{}", &text[start..end])))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Root,
+    Text(String),
+    SoftBreak,
+    HardBreak,
+    Code(String),
+    Math(String),
+    Html(String),
+    Emph,
+    Strong,
+    Del,
+    Link(String, String),
+    Image(String, String),
+    /// a `[^label]` with a definition, by normalized label
+    FootnoteRef(String),
+    /// an inline `^[...]` footnote
+    Note,
+}
+
+struct Node {
+    kind: Kind,
+    /// the range of the node in the content
+    start: usize,
+    end: usize,
+    parent: Option<usize>,
+    first: Option<usize>,
+    last: Option<usize>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// A run of `*`, `_` or `~` that may open or close emphasis.
+struct Delim {
+    node: usize,
+    ch: u8,
+    /// how many of the characters are left
+    count: usize,
+    /// how long the run was
+    length: usize,
+    can_open: bool,
+    can_close: bool,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Opener {
+    Link,
+    Image,
+    Note,
+}
+
+struct Bracket {
+    node: usize,
+    /// the top of the delimiter stack when the bracket was pushed
+    prev_delim: Option<usize>,
+    /// where the link text starts
+    index: usize,
+    kind: Opener,
+    /// cleared inside a link, which cannot contain another link
+    active: bool,
+    /// whether another bracket follows, which rules out a shortcut
+    /// reference
+    bracket_after: bool,
+}
+
+struct Parser<'a> {
+    subject: &'a str,
+    pos: usize,
+    refmap: &'a RefMap,
+    footnotes: &'a Footnotes,
+    options: &'a Options,
+    nodes: Vec<Node>,
+    delims: Vec<Delim>,
+    top: Option<usize>,
+    brackets: Vec<Bracket>,
+    /// where the pieces of merged text nodes start in the content, as their
+    /// text can be shorter than the source after entities and escapes
+    merged: HashMap<usize, Content>,
+}
+
+/// Parses `content[start..end]` into inline elements.
+pub(super) fn parse(
+    content: &Content,
+    start: usize,
+    end: usize,
+    refmap: &RefMap,
+    footnotes: &mut Footnotes,
+    options: &Options,
+    index: &LineIndex,
+) -> Text {
+    let mut p = Parser {
+        subject: &content.text[..end],
+        pos: start,
+        refmap,
+        footnotes: &*footnotes,
+        options,
+        nodes: vec![],
+        delims: vec![],
+        top: None,
+        brackets: vec![],
+        merged: HashMap::new(),
+    };
+    p.new_node(Kind::Root, start, end);
+    while p.pos < p.subject.len() {
+        p.parse_inline();
+    }
+    p.process_emphasis(None);
+    p.flatten_deep();
+    p.merge_text(0);
+    if options.autolinks {
+        p.extended_autolinks(0);
+    }
+    let nodes = std::mem::take(&mut p.nodes);
+    let mut text = Builder { nodes, content, index, footnotes }.text(0);
+    text.span = content.span(start, end, index);
+    text
+}
+
+impl<'a> Parser<'a> {
+    fn new_node(&mut self, kind: Kind, start: usize, end: usize) -> usize {
+        self.nodes.push(Node { kind, start, end, parent: None, first: None, last: None, prev: None, next: None });
+        self.nodes.len() - 1
+    }
+
+    fn append_child(&mut self, parent: usize, child: usize) {
+        self.unlink(child);
+        let last = self.nodes[parent].last;
+        self.nodes[child].parent = Some(parent);
+        self.nodes[child].prev = last;
+        match last {
+            Some(last) => self.nodes[last].next = Some(child),
+            None => self.nodes[parent].first = Some(child),
+        }
+        self.nodes[parent].last = Some(child);
+    }
+
+    fn insert_after(&mut self, node: usize, sibling: usize) {
+        self.unlink(sibling);
+        let next = self.nodes[node].next;
+        let parent = self.nodes[node].parent;
+        self.nodes[sibling].parent = parent;
+        self.nodes[sibling].prev = Some(node);
+        self.nodes[sibling].next = next;
+        self.nodes[node].next = Some(sibling);
+        match next {
+            Some(next) => self.nodes[next].prev = Some(sibling),
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent].last = Some(sibling);
+                }
+            }
+        }
+    }
+
+    fn unlink(&mut self, node: usize) {
+        let Node { parent, prev, next, .. } = self.nodes[node];
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent].first = next;
+                }
+            }
+        }
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent].last = prev;
+                }
+            }
+        }
+        let node = &mut self.nodes[node];
+        node.parent = None;
+        node.prev = None;
+        node.next = None;
+    }
+
+    fn add(&mut self, kind: Kind, start: usize, end: usize) -> usize {
+        let node = self.new_node(kind, start, end);
+        self.append_child(0, node);
+        node
+    }
+
+    fn add_text(&mut self, text: &str, start: usize, end: usize) -> usize {
+        self.add(Kind::Text(text.to_string()), start, end)
+    }
+
+    fn peek(&self, pos: usize) -> Option<u8> {
+        self.subject.as_bytes().get(pos).copied()
+    }
+
+    fn parse_inline(&mut self) {
+        let start = self.pos;
+        let c = self.subject.as_bytes()[start];
+        let handled = match c {
+            b'\n' => self.newline(),
+            b'\\' => self.backslash(),
+            b'`' => self.backticks(),
+            b'*' | b'_' => self.delimiters(c),
+            b'~' if self.options.strikethrough => self.delimiters(c),
+            b'[' if self.options.footnotes && self.footnote_reference() => true,
+            b'[' => {
+                self.pos += 1;
+                let node = self.add_text("[", start, self.pos);
+                self.push_bracket(node, Opener::Link);
+                true
+            }
+            b'!' if self.peek(start + 1) == Some(b'[') => {
+                self.pos += 2;
+                let node = self.add_text("![", start, self.pos);
+                self.push_bracket(node, Opener::Image);
+                true
+            }
+            b'^' if self.options.footnotes && self.peek(start + 1) == Some(b'[') => {
+                self.pos += 2;
+                let node = self.add_text("^[", start, self.pos);
+                self.push_bracket(node, Opener::Note);
+                true
+            }
+            b']' => self.close_bracket(),
+            b'<' => self.angle_bracket(),
+            b'&' => self.entity(),
+            b'$' if self.options.math => self.math(),
+            _ => {
+                let len = self.subject.as_bytes()[start..]
+                    .iter()
+                    .position(|x| b"\n\\`*_~[]!<&$^".contains(x))
+                    .unwrap_or(self.subject.len() - start);
+                if len > 0 {
+                    self.pos += len;
+                    self.add_text(&self.subject[start..start + len], start, self.pos);
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if !handled {
+            self.pos = start + 1;
+            self.add_text(&self.subject[start..start + 1], start, self.pos);
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while is_space_or_tab(self.peek(self.pos)) {
+            self.pos += 1;
+        }
+    }
+
+    fn newline(&mut self) -> bool {
+        let start = self.pos;
+        self.pos += 1;
+        let mut hard = false;
+        let mut break_start = start;
+        if let Some(last) = self.nodes[0].last {
+            let node = &mut self.nodes[last];
+            if let Kind::Text(text) = &mut node.kind {
+                let trimmed = text.trim_end_matches(' ').len();
+                let spaces = text.len() - trimmed;
+                if spaces > 0 {
+                    hard = spaces >= 2;
+                    text.truncate(trimmed);
+                    node.end -= spaces.min(node.end - node.start);
+                    break_start = node.end;
+                }
+            }
+        }
+        let kind = if hard { Kind::HardBreak } else { Kind::SoftBreak };
+        self.add(kind, if hard { break_start } else { start }, self.pos);
+        self.skip_spaces();
+        true
+    }
+
+    fn backslash(&mut self) -> bool {
+        let start = self.pos;
+        match self.peek(start + 1) {
+            Some(b'\n') => {
+                self.pos += 2;
+                self.add(Kind::HardBreak, start, self.pos);
+                self.skip_spaces();
+            }
+            Some(c) if c.is_ascii_punctuation() => {
+                self.pos += 2;
+                self.add_text(&self.subject[start + 1..start + 2], start, self.pos);
+            }
+            _ => {
+                self.pos += 1;
+                self.add_text("\\", start, self.pos);
+            }
+        }
+        true
+    }
+
+    fn backticks(&mut self) -> bool {
+        let b = self.subject.as_bytes();
+        let start = self.pos;
+        let ticks = b[start..].iter().take_while(|&&x| x == b'`').count();
+        let mut p = start + ticks;
+        while let Some(at) = b[p..].iter().position(|&x| x == b'`') {
+            let close = p + at;
+            let run = b[close..].iter().take_while(|&&x| x == b'`').count();
+            if run == ticks {
+                let mut code = self.subject[start + ticks..close].replace('\n', " ");
+                if code.len() >= 2 && code.starts_with(' ') && code.ends_with(' ') && code.bytes().any(|x| x != b' ') {
+                    code = code[1..code.len() - 1].to_string();
+                }
+                self.pos = close + run;
+                self.add(Kind::Code(code), start, self.pos);
+                return true;
+            }
+            p = close + run;
+        }
+        self.pos = start + ticks;
+        self.add_text(&self.subject[start..self.pos], start, self.pos);
+        true
+    }
+
+    fn math(&mut self) -> bool {
+        let b = self.subject.as_bytes();
+        let start = self.pos;
+        let run = b[start..].iter().take_while(|&&x| x == b'$').count();
+        let opens = run <= 2 && b.get(start + run).is_some_and(|x| !x.is_ascii_whitespace());
+        if opens {
+            // only the first run of the same length may close
+            let mut p = start + run;
+            while p < b.len() {
+                match b[p] {
+                    b'\\' => p += 2,
+                    b'$' => {
+                        let close = b[p..].iter().take_while(|&&x| x == b'$').count();
+                        if close != run {
+                            p += close;
+                            continue;
+                        }
+                        let valid = !b[p - 1].is_ascii_whitespace()
+                            && !b.get(p + close).is_some_and(|x| x.is_ascii_digit());
+                        if !valid {
+                            break;
+                        }
+                        let formula = self.subject[start + run..p].to_string();
+                        self.pos = p + close;
+                        self.add(Kind::Math(formula), start, self.pos);
+                        return true;
+                    }
+                    _ => p += 1,
+                }
+            }
+        }
+        self.pos = start + run;
+        self.add_text(&self.subject[start..self.pos], start, self.pos);
+        true
+    }
+
+    fn entity(&mut self) -> bool {
+        let start = self.pos;
+        match entity(&self.subject[start..]) {
+            Some((value, len)) => {
+                self.pos += len;
+                self.add(Kind::Text(value), start, self.pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn angle_bracket(&mut self) -> bool {
+        let b = self.subject.as_bytes();
+        let start = self.pos;
+        if let Some((end, email)) = autolink(b, start) {
+            let text = &self.subject[start + 1..end - 1];
+            let url = if email { format!("mailto:{}", text) } else { text.to_string() };
+            self.pos = end;
+            let link = self.add(Kind::Link(url, String::new()), start, end);
+            let child = self.new_node(Kind::Text(text.to_string()), start + 1, end - 1);
+            self.append_child(link, child);
+            return true;
+        }
+        match html_inline(b, start) {
+            Some(end) => {
+                self.pos = end;
+                self.add(Kind::Html(self.subject[start..end].to_string()), start, end);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn delimiters(&mut self, c: u8) -> bool {
+        let start = self.pos;
+        let run = self.subject.as_bytes()[start..].iter().take_while(|&&x| x == c).count();
+        let before = self.subject[..start].chars().next_back().unwrap_or('\n');
+        let after = self.subject[start + run..].chars().next().unwrap_or('\n');
+        let (before_space, after_space) = (before.is_whitespace(), after.is_whitespace());
+        let (before_punct, after_punct) = (is_punctuation(before), is_punctuation(after));
+        let left = !after_space && (!after_punct || before_space || before_punct);
+        let right = !before_space && (!before_punct || after_space || after_punct);
+        let (can_open, can_close) = if c == b'_' {
+            (left && (!right || before_punct), right && (!left || after_punct))
+        } else {
+            (left, right)
+        };
+        self.pos += run;
+        let node = self.add_text(&self.subject[start..self.pos], start, self.pos);
+        if (can_open || can_close) && (c != b'~' || run <= 2) {
+            self.delims.push(Delim {
+                node,
+                ch: c,
+                count: run,
+                length: run,
+                can_open,
+                can_close,
+                prev: self.top,
+                next: None,
+            });
+            let id = self.delims.len() - 1;
+            if let Some(top) = self.top {
+                self.delims[top].next = Some(id);
+            }
+            self.top = Some(id);
+        }
+        true
+    }
+
+    fn remove_delim(&mut self, id: usize) {
+        let (prev, next) = (self.delims[id].prev, self.delims[id].next);
+        if let Some(prev) = prev {
+            self.delims[prev].next = next;
+        }
+        match next {
+            Some(next) => self.delims[next].prev = prev,
+            None => self.top = prev,
+        }
+    }
+
+    fn push_bracket(&mut self, node: usize, kind: Opener) {
+        if let Some(last) = self.brackets.last_mut() {
+            last.bracket_after = true;
+        }
+        self.brackets.push(Bracket {
+            node,
+            prev_delim: self.top,
+            index: self.pos,
+            kind,
+            active: true,
+            bracket_after: false,
+        });
+    }
+
+    /// Reads a `[^label]` referencing a footnote definition at the current
+    /// position.
+    fn footnote_reference(&mut self) -> bool {
+        let start = self.pos;
+        let b = self.subject.as_bytes();
+        if b.get(start + 1) != Some(&b'^') {
+            return false;
+        }
+        let len = b[start + 2..]
+            .iter()
+            .take(MAX_LABEL + 1)
+            .take_while(|&&x| !x.is_ascii_whitespace() && x != b'[' && x != b']')
+            .count();
+        if len == 0 || len > MAX_LABEL || b.get(start + 2 + len) != Some(&b']') {
+            return false;
+        }
+        let label = normalize_label(&self.subject[start + 2..start + 2 + len]);
+        if !self.footnotes.defined.contains_key(&label) {
+            return false;
+        }
+        self.pos = start + 3 + len;
+        self.add(Kind::FootnoteRef(label), start, self.pos);
+        true
+    }
+
+    /// The destination and title of an inline link after the `]` at the
+    /// current position, moving past it.
+    fn inline_link(&mut self) -> Option<(String, String)> {
+        let b = self.subject.as_bytes();
+        if b.get(self.pos) != Some(&b'(') {
+            return None;
+        }
+        let pos = spnl(b, self.pos + 1);
+        let (url, after_url) = link_destination(self.subject, pos)?;
+        let title_pos = spnl(b, after_url);
+        let (title, after_title) = if title_pos > after_url {
+            link_title(self.subject, title_pos).unwrap_or((String::new(), title_pos))
+        } else {
+            (String::new(), title_pos)
+        };
+        let end = spnl(b, after_title);
+        if b.get(end) != Some(&b')') {
+            return None;
+        }
+        self.pos = end + 1;
+        Some((url, title))
+    }
+
+    fn close_bracket(&mut self) -> bool {
+        let start = self.pos;
+        self.pos += 1;
+        let after = self.pos;
+        let opener = match self.brackets.last() {
+            Some(x) => x,
+            None => return false,
+        };
+        if !opener.active {
+            self.brackets.pop();
+            return false;
+        }
+        let (opener_kind, index, bracket_after, opener_node, prev_delim) =
+            (opener.kind, opener.index, opener.bracket_after, opener.node, opener.prev_delim);
+
+        let kind = if opener_kind == Opener::Note {
+            Kind::Note
+        } else {
+            let mut matched = self.inline_link();
+            if matched.is_none() {
+                let label_len = link_label(self.subject.as_bytes(), after);
+                let label = if label_len > 2 {
+                    Some(&self.subject[after + 1..after + label_len - 1])
+                } else if !bracket_after {
+                    Some(&self.subject[index..start])
+                } else {
+                    None
+                };
+                if label_len > 0 {
+                    self.pos = after + label_len;
+                }
+                if let Some(def) = label.and_then(|x| self.refmap.get(&normalize_label(x))) {
+                    matched = Some((def.url.clone(), def.title.clone()));
+                }
+            }
+            match matched {
+                Some((url, title)) if opener_kind == Opener::Image => Kind::Image(url, title),
+                Some((url, title)) => Kind::Link(url, title),
+                None => {
+                    self.brackets.pop();
+                    self.pos = after;
+                    return false;
+                }
+            }
+        };
+        let link = self.new_node(kind, self.nodes[opener_node].start, self.pos);
+        while let Some(next) = self.nodes[opener_node].next {
+            self.append_child(link, next);
+        }
+        self.append_child(0, link);
+        self.process_emphasis(prev_delim);
+        self.brackets.pop();
+        self.unlink(opener_node);
+        if opener_kind == Opener::Link {
+            // links cannot contain other links
+            for x in self.brackets.iter_mut().filter(|x| x.kind == Opener::Link) {
+                x.active = false;
+            }
+        }
+        true
+    }
+
+    /// Matches up the delimiters above `bottom` into emphasis.
+    fn process_emphasis(&mut self, bottom: Option<usize>) {
+        let mut openers_bottom = [[bottom; 6]; 3];
+        let slot = |c: u8| match c {
+            b'*' => 0,
+            b'_' => 1,
+            _ => 2,
+        };
+
+        let mut closer = self.top;
+        while let Some(c) = closer {
+            if self.delims[c].prev == bottom {
+                break;
+            }
+            closer = self.delims[c].prev;
+        }
+
+        while let Some(c) = closer {
+            let d = &self.delims[c];
+            if !d.can_close {
+                closer = d.next;
+                continue;
+            }
+            let (ch, bottom_index) = (d.ch, (d.can_open as usize) * 3 + d.length % 3);
+            let mut opener = d.prev;
+            let mut found = None;
+            while let Some(o) = opener {
+                if opener == bottom || opener == openers_bottom[slot(ch)][bottom_index] {
+                    break;
+                }
+                let od = &self.delims[o];
+                if od.ch == ch && od.can_open {
+                    let both = od.length + d.length;
+                    let odd_match = ch != b'~'
+                        && (d.can_open || od.can_close)
+                        && both.is_multiple_of(3)
+                        && !(od.length.is_multiple_of(3) && d.length.is_multiple_of(3));
+                    if !odd_match && (ch != b'~' || od.length == d.length) {
+                        found = Some(o);
+                        break;
+                    }
+                }
+                opener = od.prev;
+            }
+
+            let o = match found {
+                Some(o) => o,
+                None => {
+                    closer = self.delims[c].next;
+                    openers_bottom[slot(ch)][bottom_index] = self.delims[c].prev;
+                    if !self.delims[c].can_open {
+                        self.remove_delim(c);
+                    }
+                    continue;
+                }
+            };
+
+            let used = if ch == b'~' {
+                self.delims[c].count
+            } else if self.delims[c].count >= 2 && self.delims[o].count >= 2 {
+                2
+            } else {
+                1
+            };
+            self.delims[o].count -= used;
+            self.delims[c].count -= used;
+            let (opener_node, closer_node) = (self.delims[o].node, self.delims[c].node);
+            if let Kind::Text(text) = &mut self.nodes[opener_node].kind {
+                text.truncate(text.len() - used);
+            }
+            if let Kind::Text(text) = &mut self.nodes[closer_node].kind {
+                text.drain(..used);
+            }
+            self.nodes[opener_node].end -= used;
+            self.nodes[closer_node].start += used;
+
+            let kind = match (ch, used) {
+                (b'~', _) => Kind::Del,
+                (_, 1) => Kind::Emph,
+                _ => Kind::Strong,
+            };
+            // the emphasis spans the delimiters it used
+            let emph = self.new_node(kind, self.nodes[opener_node].end, self.nodes[closer_node].start);
+            while let Some(next) = self.nodes[opener_node].next {
+                if next == closer_node {
+                    break;
+                }
+                self.append_child(emph, next);
+            }
+            self.insert_after(opener_node, emph);
+
+            self.delims[o].next = Some(c);
+            self.delims[c].prev = Some(o);
+            if self.delims[o].count == 0 {
+                self.unlink(opener_node);
+                self.remove_delim(o);
+            }
+            if self.delims[c].count == 0 {
+                let next = self.delims[c].next;
+                self.unlink(closer_node);
+                self.remove_delim(c);
+                closer = next;
+            }
+        }
+
+        while let Some(top) = self.top {
+            if Some(top) == bottom {
+                break;
+            }
+            self.remove_delim(top);
+        }
+    }
+
+
+    /// Replaces the elements nested too deeply by their children, without
+    /// recursing.
+    fn flatten_deep(&mut self) {
+        let mut stack = vec![(0, 0)];
+        while let Some((parent, depth)) = stack.pop() {
+            let mut child = self.nodes[parent].first;
+            while let Some(c) = child {
+                let next = self.nodes[c].next;
+                if depth >= MAX_NESTING && self.nodes[c].first.is_some() {
+                    let first = self.nodes[c].first;
+                    let mut last = c;
+                    while let Some(x) = self.nodes[c].first {
+                        self.insert_after(last, x);
+                        last = x;
+                    }
+                    self.unlink(c);
+                    child = first;
+                    continue;
+                }
+                stack.push((c, depth + 1));
+                child = next;
+            }
+        }
+    }
+
+    /// Joins adjacent text nodes and drops empty ones.
+    fn merge_text(&mut self, parent: usize) {
+        let mut previous: Option<usize> = None;
+        for c in children(&self.nodes, parent) {
+            let text = match &self.nodes[c].kind {
+                Kind::Text(text) => text.clone(),
+                _ => {
+                    self.merge_text(c);
+                    previous = None;
+                    continue;
+                }
+            };
+            if text.is_empty() {
+                self.unlink(c);
+                continue;
+            }
+            match previous {
+                Some(p) => {
+                    let (start, piece_start) = (self.nodes[p].start, self.nodes[c].start);
+                    if let Kind::Text(previous_text) = &mut self.nodes[p].kind {
+                        let pieces = self.merged.entry(p).or_insert_with(|| {
+                            let mut pieces = Content::default();
+                            pieces.push(previous_text, start);
+                            pieces
+                        });
+                        pieces.push(&text, piece_start);
+                        previous_text.push_str(&text);
+                    }
+                    self.nodes[p].end = self.nodes[c].end;
+                    self.unlink(c);
+                }
+                None => previous = Some(c),
+            }
+        }
+    }
+
+    /// Turns bare urls and email addresses outside of links into links.
+    fn extended_autolinks(&mut self, parent: usize) {
+        for c in children(&self.nodes, parent) {
+            match &self.nodes[c].kind {
+                Kind::Text(_) => self.autolink_text(c),
+                Kind::Link(..) | Kind::Image(..) => {}
+                _ => self.extended_autolinks(c),
+            }
+        }
+    }
+
+    fn autolink_text(&mut self, node: usize) {
+        let text = match &self.nodes[node].kind {
+            Kind::Text(text) => text.clone(),
+            _ => return,
+        };
+        let mut links = vec![];
+        let mut from = 0;
+        for (i, c) in text.bytes().enumerate() {
+            if i < from || !b"w:@".contains(&c) {
+                continue;
+            }
+            if let Some((start, end, url)) = extended_autolink(&text, i) {
+                if start >= from {
+                    links.push((start, end, url));
+                    from = end;
+                }
+            }
+        }
+        if links.is_empty() {
+            return;
+        }
+
+        let Node { start: node_start, end: node_end, .. } = self.nodes[node];
+        let pieces = self.merged.remove(&node).unwrap_or_else(|| {
+            let mut pieces = Content::default();
+            pieces.push(&text, node_start);
+            pieces
+        });
+        // an offset inside a decoded entity can point past its source
+        let source = |i: usize| pieces.source(i).min(node_end);
+        let mut last = node;
+        let mut done = 0;
+        for (start, end, url) in links {
+            if start > done {
+                let before = self.new_node(Kind::Text(text[done..start].to_string()), source(done), source(start));
+                self.insert_after(last, before);
+                last = before;
+            }
+            let link = self.new_node(Kind::Link(url, String::new()), source(start), source(end));
+            let link_text = self.new_node(Kind::Text(text[start..end].to_string()), source(start), source(end));
+            self.append_child(link, link_text);
+            self.insert_after(last, link);
+            last = link;
+            done = end;
+        }
+        if done < text.len() {
+            let rest = self.new_node(Kind::Text(text[done..].to_string()), source(done), node_end);
+            self.insert_after(last, rest);
+        }
+        self.unlink(node);
+    }
+}
+
+fn children(nodes: &[Node], parent: usize) -> Vec<usize> {
+    let mut children = vec![];
+    let mut child = nodes[parent].first;
+    while let Some(c) = child {
+        children.push(c);
+        child = nodes[c].next;
+    }
+    children
+}
+
+/// Turns the node tree into the AST, numbering footnotes on the way.
+struct Builder<'a> {
+    nodes: Vec<Node>,
+    content: &'a Content,
+    index: &'a LineIndex,
+    footnotes: &'a mut Footnotes,
+}
+
+impl<'a> Builder<'a> {
+    /// The plain text of the children of `parent`, for the alt of an image.
+    fn plain_text(&self, parent: usize, out: &mut String) {
+        for c in children(&self.nodes, parent) {
+            match &self.nodes[c].kind {
+                Kind::Text(x) | Kind::Code(x) | Kind::Math(x) | Kind::Html(x) => out.push_str(x),
+                Kind::SoftBreak | Kind::HardBreak => out.push(' '),
+                _ => self.plain_text(c, out),
+            }
+        }
+    }
+
+    fn text(&mut self, parent: usize) -> Text {
+        let nodes: Vec<Inline> = children(&self.nodes, parent).into_iter().map(|x| self.inline(x)).collect();
+        let span = match (nodes.first(), nodes.last()) {
+            (Some(first), Some(last)) => Span { start: first.span().start, end: last.span().end },
+            _ => {
+                let node = &self.nodes[parent];
+                self.content.span(node.start, node.start, self.index)
+            }
+        };
+        Text { nodes, span }
+    }
+
+    fn inline(&mut self, id: usize) -> Inline {
+        let node = &self.nodes[id];
+        let span = self.content.span(node.start, node.end, self.index);
+        match node.kind.clone() {
+            Kind::Text(text) => Inline::Plain(Plain { text, span }),
+            Kind::SoftBreak => Inline::SoftBreak(SoftBreak { span }),
+            Kind::HardBreak => Inline::HardBreak(HardBreak { span }),
+            Kind::Code(code) => Inline::CodeInline(CodeInline { code, span }),
+            Kind::Math(formula) => Inline::MathInline(MathInline { formula, span }),
+            Kind::Html(html) => Inline::HtmlInline(HtmlInline { html, span }),
+            Kind::Emph => Inline::Italic(Italic { text: self.text(id), span }),
+            Kind::Strong => Inline::Bold(Bold { text: self.text(id), span }),
+            Kind::Del => Inline::Deleted(Deleted { text: self.text(id), span }),
+            Kind::Link(url, title) => Inline::Url(Url { text: self.text(id), url, title, span }),
+            Kind::Image(url, title) => {
+                let mut alt = String::new();
+                self.plain_text(id, &mut alt);
+                Inline::Image(Image { alt, url, title, span })
+            }
+            Kind::FootnoteRef(label) => {
+                let (number, index) = self.footnotes.reference(&label);
+                Inline::FootnoteReference(FootnoteReference { number, index, span })
+            }
+            Kind::Note => {
+                // numbered before the notes inside it
+                let number = self.footnotes.add("");
+                let text = self.text(id);
+                let note = &mut self.footnotes.notes[number - 1];
+                note.blocks = vec![Block::PlainParagraph(PlainParagraph { span: text.span, text })];
+                note.references = 1;
+                note.span = span;
+                Inline::FootnoteReference(FootnoteReference { number, index: 1, span })
+            }
+            Kind::Root => Inline::Plain(Plain::default()),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+/// A location in the source text.
+///
+/// `offset` is a byte offset, `line` and `column` start from 1 and the
+/// column is counted in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The source range a node was parsed from. `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// Byte offsets of the line starts, for turning offsets into positions.
+pub(crate) struct LineIndex {
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub(crate) fn new(text: &[u8]) -> LineIndex {
+        let mut starts = vec![0];
+        starts.extend(text.iter().enumerate().filter(|(_, &c)| c == b'\n').map(|(i, _)| i + 1));
+        LineIndex { starts, len: text.len() }
+    }
+
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.len);
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        Position {
+            offset,
+            line: line + 1,
+            column: offset - self.starts[line] + 1,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Turning a document into output.
+
+use crate::ast::*;
+use crate::buf::Buf;
+use crate::html;
+use crate::options::{Options, DEFAULT_OPTIONS};
+
+/// Writes a document into a [`Buf`], one hook per node kind.
+///
+/// Every hook defaults to the HTML of [`HtmlRenderer`](crate::HtmlRenderer),
+/// and container hooks render their children through `self`, so overriding a
+/// single hook changes that element wherever it occurs:
+///
+/// ```
+/// use markdowner::ast::CodeBlock;
+/// use markdowner::{Buf, Renderer};
+///
+/// struct Highlighted;
+///
+/// impl Renderer for Highlighted {
+///     fn render_code_block(&mut self, node: &CodeBlock, buf: &mut dyn Buf) {
+///         buf.push_str("<x-code>");
+///         buf.push_str(&node.text);
+///         buf.push_str("</x-code>");
+///     }
+/// }
+///
+/// let doc = markdowner::parse("# Code\n```rust\nfn main() {}\n```")?;
+/// let mut buf: Vec<u8> = vec![];
+/// Highlighted.render_document(&doc, &mut buf);
+/// assert_eq!(buf, b"<h1>Code</h1>\n<x-code>fn main() {}\n</x-code>".to_vec());
+/// # Ok::<(), markdowner::ParseError>(())
+/// ```
+pub trait Renderer {
+    /// The options the default hooks render with.
+    fn options(&self) -> &Options {
+        &DEFAULT_OPTIONS
+    }
+
+    fn render_document(&mut self, node: &Document, buf: &mut dyn Buf) {
+        html::document(self, node, buf)
+    }
+
+    fn render_block(&mut self, node: &Block, buf: &mut dyn Buf) {
+        match node {
+            Block::PlainParagraph(x) => self.render_plain_paragraph(x, buf),
+            Block::Heading(x) => self.render_heading(x, buf),
+            Block::ThematicBreak(x) => self.render_thematic_break(x, buf),
+            Block::OrderedList(x) => self.render_ordered_list(x, buf),
+            Block::UnorderedList(x) => self.render_unordered_list(x, buf),
+            Block::Quote(x) => self.render_quote(x, buf),
+            Block::CodeBlock(x) => self.render_code_block(x, buf),
+            Block::HtmlBlock(x) => self.render_html_block(x, buf),
+            Block::Table(x) => self.render_table(x, buf),
+            Block::MathDisplay(x) => self.render_math_display(x, buf),
+        }
+    }
+
+    fn render_plain_paragraph(&mut self, node: &PlainParagraph, buf: &mut dyn Buf) {
+        html::plain_paragraph(self, node, buf)
+    }
+
+    fn render_heading(&mut self, node: &Heading, buf: &mut dyn Buf) {
+        html::heading(self, node, buf)
+    }
+
+    fn render_thematic_break(&mut self, node: &ThematicBreak, buf: &mut dyn Buf) {
+        html::thematic_break(self, node, buf)
+    }
+
+    fn render_ordered_list(&mut self, node: &OrderedList, buf: &mut dyn Buf) {
+        html::ordered_list(self, node, buf)
+    }
+
+    fn render_unordered_list(&mut self, node: &UnorderedList, buf: &mut dyn Buf) {
+        html::unordered_list(self, node, buf)
+    }
+
+    /// Renders an item of a list; the paragraphs of items in a `tight`
+    /// list are written without `<p>` tags.
+    fn render_list_item(&mut self, node: &ListItem, tight: bool, buf: &mut dyn Buf) {
+        html::list_item(self, node, tight, buf)
+    }
+
+    fn render_quote(&mut self, node: &Quote, buf: &mut dyn Buf) {
+        html::quote(self, node, buf)
+    }
+
+    fn render_code_block(&mut self, node: &CodeBlock, buf: &mut dyn Buf) {
+        html::code_block(self, node, buf)
+    }
+
+    fn render_html_block(&mut self, node: &HtmlBlock, buf: &mut dyn Buf) {
+        html::html_block(self, node, buf)
+    }
+
+    fn render_table(&mut self, node: &Table, buf: &mut dyn Buf) {
+        html::table(self, node, buf)
+    }
+
+    fn render_math_display(&mut self, node: &MathDisplay, buf: &mut dyn Buf) {
+        html::math_display(self, node, buf)
+    }
+
+    /// Renders the footnotes after the rest of the document; only called
+    /// if there are any.
+    fn render_footnotes(&mut self, nodes: &[Footnote], buf: &mut dyn Buf) {
+        html::footnotes(self, nodes, buf)
+    }
+
+    fn render_footnote(&mut self, node: &Footnote, buf: &mut dyn Buf) {
+        html::footnote(self, node, buf)
+    }
+
+    fn render_text(&mut self, node: &Text, buf: &mut dyn Buf) {
+        for x in &node.nodes {
+            self.render_inline(x, buf);
+        }
+    }
+
+    fn render_inline(&mut self, node: &Inline, buf: &mut dyn Buf) {
+        match node {
+            Inline::Plain(x) => self.render_plain(x, buf),
+            Inline::Bold(x) => self.render_bold(x, buf),
+            Inline::Italic(x) => self.render_italic(x, buf),
+            Inline::Deleted(x) => self.render_deleted(x, buf),
+            Inline::CodeInline(x) => self.render_code_inline(x, buf),
+            Inline::MathInline(x) => self.render_math_inline(x, buf),
+            Inline::Image(x) => self.render_image(x, buf),
+            Inline::Url(x) => self.render_url(x, buf),
+            Inline::HtmlInline(x) => self.render_html_inline(x, buf),
+            Inline::SoftBreak(x) => self.render_soft_break(x, buf),
+            Inline::HardBreak(x) => self.render_hard_break(x, buf),
+            Inline::FootnoteReference(x) => self.render_footnote_reference(x, buf),
+        }
+    }
+
+    fn render_plain(&mut self, node: &Plain, buf: &mut dyn Buf) {
+        html::plain(self, node, buf)
+    }
+
+    fn render_bold(&mut self, node: &Bold, buf: &mut dyn Buf) {
+        html::bold(self, node, buf)
+    }
+
+    fn render_italic(&mut self, node: &Italic, buf: &mut dyn Buf) {
+        html::italic(self, node, buf)
+    }
+
+    fn render_deleted(&mut self, node: &Deleted, buf: &mut dyn Buf) {
+        html::deleted(self, node, buf)
+    }
+
+    fn render_code_inline(&mut self, node: &CodeInline, buf: &mut dyn Buf) {
+        html::code_inline(self, node, buf)
+    }
+
+    fn render_math_inline(&mut self, node: &MathInline, buf: &mut dyn Buf) {
+        html::math_inline(self, node, buf)
+    }
+
+    fn render_image(&mut self, node: &Image, buf: &mut dyn Buf) {
+        html::image(self, node, buf)
+    }
+
+    fn render_url(&mut self, node: &Url, buf: &mut dyn Buf) {
+        html::url(self, node, buf)
+    }
+
+    fn render_html_inline(&mut self, node: &HtmlInline, buf: &mut dyn Buf) {
+        html::html_inline(self, node, buf)
+    }
+
+    fn render_soft_break(&mut self, node: &SoftBreak, buf: &mut dyn Buf) {
+        html::soft_break(self, node, buf)
+    }
+
+    fn render_hard_break(&mut self, node: &HardBreak, buf: &mut dyn Buf) {
+        html::hard_break(self, node, buf)
+    }
+
+    fn render_footnote_reference(&mut self, node: &FootnoteReference, buf: &mut dyn Buf) {
+        html::footnote_reference(self, node, buf)
+    }
+}
@@ -0,0 +1,285 @@
+//! Restricting the HTML rendered from untrusted markdown.
+
+use crate::buf::Buf;
+use crate::escape::{escape_href, escape_html};
+use crate::parser::decode_references;
+
+/// Tags that can run script or change how the page loads, whatever the
+/// allowlist says.
+const FORBIDDEN_TAGS: &[&str] = &[
+    "base", "embed", "frame", "frameset", "iframe", "link", "meta", "noscript", "object",
+    "script", "style", "template",
+];
+
+/// Attributes holding a url, with or without a namespace prefix as in
+/// `xlink:href`; their values must pass [`SafeMode::is_safe_url`].
+const URL_ATTRIBUTES: &[&str] = &["action", "background", "cite", "formaction", "href", "poster", "src"];
+
+/// Allowlists for rendering markdown from untrusted sources.
+///
+/// Links and images whose url has a scheme outside `url_schemes` lose their
+/// url, raw HTML tags outside `tags` are escaped and attributes outside
+/// `attributes` are dropped. Script tags, `on*` event handlers and `style` or
+/// `srcdoc` attributes are never let through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafeMode {
+    /// raw HTML tags kept as they are, lowercase
+    pub tags: Vec<String>,
+    /// attributes kept on the allowed tags, lowercase
+    pub attributes: Vec<String>,
+    /// url schemes allowed in links, images and url attributes, lowercase;
+    /// urls without a scheme are always allowed
+    pub url_schemes: Vec<String>,
+}
+
+impl Default for SafeMode {
+    fn default() -> Self {
+        SafeMode {
+            tags: vec![],
+            attributes: vec![],
+            url_schemes: ["http", "https", "mailto"].iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+impl SafeMode {
+    /// Whether `url` has no scheme or an allowed one.
+    ///
+    /// Whitespace and control characters are ignored the way browsers
+    /// ignore them, so `java\tscript:` counts as `javascript:`.
+    pub fn is_safe_url(&self, url: &str) -> bool {
+        let url: String = url.chars().filter(|c| !c.is_ascii_whitespace() && !c.is_control()).collect();
+        match scheme(&url) {
+            Some(scheme) => self.url_schemes.iter().any(|x| x.eq_ignore_ascii_case(scheme)),
+            None => true,
+        }
+    }
+
+    fn is_allowed_tag(&self, name: &str) -> bool {
+        !FORBIDDEN_TAGS.contains(&name) && self.tags.iter().any(|x| x == name)
+    }
+
+    fn is_allowed_attribute(&self, name: &str) -> bool {
+        !name.starts_with("on")
+            && name != "style"
+            && name != "srcdoc"
+            && self.attributes.iter().any(|x| x == name)
+    }
+}
+
+/// The scheme of `url`, if it has one.
+fn scheme(url: &str) -> Option<&str> {
+    let end = url.find([':', '/', '?', '#'])?;
+    let scheme = &url[..end];
+    let mut chars = scheme.chars();
+    let valid = url[end..].starts_with(':')
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if valid {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// Writes the raw HTML `html`, keeping only what `safe` allows.
+///
+/// Allowed tags are written back normalized with their allowed attributes;
+/// any other tag, comment or declaration is escaped so it shows as text.
+pub fn sanitize_html(html: &str, safe: &SafeMode, buf: &mut dyn Buf) {
+    let mut pos = 0;
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+        buf.push_str(&html[pos..start]);
+        match tag(html, start) {
+            Some((tag, end)) if safe.is_allowed_tag(&tag.name) => {
+                write_tag(&tag, safe, buf);
+                pos = end;
+            }
+            _ => {
+                buf.push_str("&lt;");
+                pos = start + 1;
+            }
+        }
+    }
+    buf.push_str(&html[pos..]);
+}
+
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, Option<&'a str>)>,
+}
+
+fn write_tag(tag: &Tag, safe: &SafeMode, buf: &mut dyn Buf) {
+    buf.push_str(if tag.closing { "</" } else { "<" });
+    buf.push_str(&tag.name);
+    for (name, value) in &tag.attributes {
+        if !safe.is_allowed_attribute(name) {
+            continue;
+        }
+        // check and escape the value the browser sees, not its encoding
+        let value = value.map(decode_references);
+        let local_name = name.rsplit(':').next().unwrap_or(name);
+        let is_url = URL_ATTRIBUTES.contains(&local_name);
+        if is_url && !value.as_deref().is_none_or(|x| safe.is_safe_url(x)) {
+            continue;
+        }
+        buf.push_char(' ');
+        buf.push_str(name);
+        if let Some(value) = &value {
+            buf.push_str("=\"");
+            if is_url {
+                escape_href(value, buf);
+            } else {
+                escape_html(value, buf);
+            }
+            buf.push_char('"');
+        }
+    }
+    buf.push_str(if tag.self_closing { " />" } else { ">" });
+}
+
+/// Reads an open or closing tag starting at the `<` at `pos`.
+fn tag(html: &str, pos: usize) -> Option<(Tag<'_>, usize)> {
+    let bytes = html.as_bytes();
+    let mut pos = pos + 1;
+    let closing = bytes.get(pos) == Some(&b'/');
+    if closing {
+        pos += 1;
+    }
+    let name_start = pos;
+    if !bytes.get(pos)?.is_ascii_alphabetic() {
+        return None;
+    }
+    while bytes.get(pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'-') {
+        pos += 1;
+    }
+    let name = html[name_start..pos].to_ascii_lowercase();
+    let mut attributes = vec![];
+    loop {
+        let space_start = pos;
+        while bytes.get(pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        match bytes.get(pos)? {
+            b'>' => {
+                let tag = Tag { name, closing, self_closing: false, attributes };
+                return Some((tag, pos + 1));
+            }
+            b'/' if !closing && bytes.get(pos + 1) == Some(&b'>') => {
+                let tag = Tag { name, closing, self_closing: true, attributes };
+                return Some((tag, pos + 2));
+            }
+            c if !closing && pos > space_start && (c.is_ascii_alphabetic() || *c == b'_' || *c == b':') => {
+                let attr_start = pos;
+                while bytes.get(pos).is_some_and(|c| c.is_ascii_alphanumeric() || b"_.:-".contains(c)) {
+                    pos += 1;
+                }
+                let attr = html[attr_start..pos].to_ascii_lowercase();
+                let mut value_pos = pos;
+                while bytes.get(value_pos).is_some_and(|c| c.is_ascii_whitespace()) {
+                    value_pos += 1;
+                }
+                if bytes.get(value_pos) != Some(&b'=') {
+                    attributes.push((attr, None));
+                    continue;
+                }
+                pos = value_pos + 1;
+                while bytes.get(pos).is_some_and(|c| c.is_ascii_whitespace()) {
+                    pos += 1;
+                }
+                let value = match bytes.get(pos)? {
+                    quote @ b'"' | quote @ b'\'' => {
+                        let len = html[pos + 1..].find(*quote as char)?;
+                        let value = &html[pos + 1..pos + 1 + len];
+                        pos += len + 2;
+                        value
+                    }
+                    _ => {
+                        let value_start = pos;
+                        while bytes.get(pos).is_some_and(|c| !c.is_ascii_whitespace() && !b"\"'=<>`".contains(c)) {
+                            pos += 1;
+                        }
+                        if pos == value_start {
+                            return None;
+                        }
+                        &html[value_start..pos]
+                    }
+                };
+                attributes.push((attr, Some(value)));
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitized(html: &str, safe: &SafeMode) -> String {
+        let mut buf: Vec<u8> = vec![];
+        sanitize_html(html, safe, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn checks_url_schemes() {
+        let safe = SafeMode::default();
+        assert!(safe.is_safe_url("https://example.com"));
+        assert!(safe.is_safe_url("/relative/path:with-colon"));
+        assert!(safe.is_safe_url("MAILTO:me@example.com"));
+        assert!(!safe.is_safe_url("javascript:alert(1)"));
+        assert!(!safe.is_safe_url(" java\tscript:alert(1)"));
+        assert!(!safe.is_safe_url("data:text/html,<script>"));
+        assert!(!safe.is_safe_url("VBScript:msgbox"));
+    }
+
+    #[test]
+    fn keeps_only_allowed_tags_and_attributes() {
+        let safe = SafeMode {
+            tags: vec!["a".to_string(), "b".to_string(), "script".to_string()],
+            attributes: vec!["href".to_string(), "onclick".to_string(), "title".to_string()],
+            ..SafeMode::default()
+        };
+        assert_eq!(
+            sanitized("<B title='x\"y' class=c>hi</b> <i>no</i>", &safe),
+            "<b title=\"x&quot;y\">hi</b> &lt;i>no&lt;/i>"
+        );
+        assert_eq!(
+            sanitized("<a href=\"javascript:x\" onclick=\"x()\">a</a><script>x()</script>", &safe),
+            "<a>a</a>&lt;script>x()&lt;/script>"
+        );
+        assert_eq!(sanitized("<!-- c --> 1 < 2 <a href=/x>", &safe), "&lt;!-- c --> 1 &lt; 2 <a href=\"/x\">");
+    }
+
+    #[test]
+    fn decodes_attribute_values_once() {
+        let safe = SafeMode {
+            tags: vec!["a".to_string(), "b".to_string()],
+            attributes: vec!["href".to_string(), "title".to_string()],
+            ..SafeMode::default()
+        };
+        assert_eq!(sanitized("<b title=\"a &amp; b &lt;&#33;\">", &safe), "<b title=\"a &amp; b &lt;!\">");
+        assert_eq!(sanitized("<a href=\"?a=1&amp;b=2\">", &safe), "<a href=\"?a=1&amp;b=2\">");
+        // schemes hidden behind character references are still caught
+        assert_eq!(sanitized("<a href=\"&#106;avascript:x\">", &safe), "<a>");
+        assert_eq!(sanitized("<a href=\"java&Tab;script&colon;x\">", &safe), "<a>");
+        assert_eq!(sanitized("<a href=\"&#x6A;avascript:x\">", &safe), "<a>");
+        // what isn't a complete reference stays text, so it can't become one
+        assert_eq!(sanitized("<a href=\"&#106avascript:x\">", &safe), "<a href=\"&amp;#106avascript:x\">");
+    }
+
+    #[test]
+    fn checks_namespaced_url_attributes() {
+        let safe = SafeMode {
+            tags: vec!["svg".to_string(), "a".to_string()],
+            attributes: vec!["xlink:href".to_string()],
+            ..SafeMode::default()
+        };
+        assert_eq!(sanitized("<svg><a xlink:href=\"javascript:alert(1)\">", &safe), "<svg><a>");
+        assert_eq!(sanitized("<a XLINK:HREF=\"/x\">", &safe), "<a xlink:href=\"/x\">");
+    }
+}
@@ -0,0 +1,126 @@
+//! Progress of the task list items in a document.
+
+use crate::ast::*;
+use crate::visit::{walk_list_item, Visitor};
+
+/// How many task list items there are, and how many of them are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TaskCount {
+    pub completed: usize,
+    pub total: usize,
+}
+
+impl TaskCount {
+    fn add(&mut self, other: TaskCount) {
+        self.completed += other.completed;
+        self.total += other.total;
+    }
+}
+
+impl Visitor for TaskCount {
+    fn visit_list_item(&mut self, node: &ListItem) {
+        if let Some(checked) = node.checked {
+            self.total += 1;
+            self.completed += checked as usize;
+        }
+        walk_list_item(self, node)
+    }
+}
+
+/// The tasks under a heading.
+///
+/// A section runs up to the next heading of the same or a higher rank, so it
+/// includes the tasks of its subsections. Only headings directly in the
+/// document start a section; one inside a quote or a list is part of the
+/// section around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section<'a> {
+    pub heading: &'a Heading,
+    pub tasks: TaskCount,
+}
+
+impl Document {
+    /// Counts the task list items of the whole document.
+    pub fn task_count(&self) -> TaskCount {
+        let mut count = TaskCount::default();
+        count.visit_document(self);
+        count
+    }
+
+    /// Counts the task list items under every heading, in document order.
+    ///
+    /// Unlike [`Document::task_count`] this leaves out the tasks before the
+    /// first heading and the tasks in footnotes, which are in no section.
+    pub fn task_sections(&self) -> Vec<Section<'_>> {
+        let mut sections: Vec<Section> = vec![];
+        // indices of the sections still open, by increasing rank
+        let mut open: Vec<usize> = vec![];
+        for x in &self.blocks {
+            match x {
+                Block::Heading(h) => {
+                    while open.last().is_some_and(|&i| sections[i].heading.rank >= h.rank) {
+                        open.pop();
+                    }
+                    open.push(sections.len());
+                    sections.push(Section { heading: h, tasks: TaskCount::default() });
+                }
+                x => {
+                    let mut count = TaskCount::default();
+                    count.visit_block(x);
+                    for &i in &open {
+                        sections[i].tasks.add(count);
+                    }
+                }
+            }
+        }
+        sections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(completed: usize, total: usize) -> TaskCount {
+        TaskCount { completed, total }
+    }
+
+    #[test]
+    fn counts_tasks_per_section() {
+        let doc = crate::parse(
+            "- [x] setup\n\n# A\n- [ ] a\n- [X] b\n  - [x] c\n- plain\n\n\
+             ## A.1\n> 1. [ ] d\n\n# B\ntext\n",
+        )
+        .unwrap();
+        assert_eq!(doc.task_count(), count(3, 5));
+        let sections: Vec<_> = doc.task_sections().iter().map(|x| (x.heading.rank, x.tasks)).collect();
+        assert_eq!(sections, [(1, count(2, 4)), (2, count(0, 1)), (1, count(0, 0))]);
+    }
+
+    #[test]
+    fn sections_leave_out_tasks_outside_them() {
+        let doc = crate::parse(
+            "- [x] before\n\n# A\n- [ ] a[^n]\n> # Quoted\n> - [x] b\n\n## A.1\n- [x] c\n\n\
+             # B\n- [ ] d\n\n[^n]: - [x] note\n",
+        )
+        .unwrap();
+        let sections = doc.task_sections();
+        let headings: Vec<_> = sections.iter().map(|x| (x.heading.rank, x.tasks)).collect();
+        assert_eq!(headings, [(1, count(2, 3)), (2, count(1, 1)), (1, count(0, 1))]);
+
+        let mut outside = TaskCount::default();
+        outside.visit_block(&doc.blocks[0]);
+        doc.footnotes.iter().for_each(|x| outside.visit_footnote(x));
+        assert_eq!(outside, count(2, 2));
+        // the sections of the highest rank cover everything else
+        let mut total = outside;
+        sections.iter().filter(|x| x.heading.rank == 1).for_each(|x| total.add(x.tasks));
+        assert_eq!(total, doc.task_count());
+    }
+
+    #[test]
+    fn tasks_are_an_extension() {
+        let doc = crate::parse_with_options("- [x] a\n", &crate::Options::commonmark()).unwrap();
+        assert_eq!(doc.task_count(), count(0, 0));
+    }
+}
@@ -0,0 +1,467 @@
+//! Traversal of the document tree.
+
+use crate::ast::*;
+
+/// Walks a document read-only.
+///
+/// Every method defaults to walking into the children of its node, so an
+/// implementation only overrides the nodes it is interested in and calls the
+/// matching `walk_*` function if it still wants to descend.
+pub trait Visitor {
+    fn visit_document(&mut self, node: &Document) {
+        walk_document(self, node)
+    }
+
+    fn visit_block(&mut self, node: &Block) {
+        walk_block(self, node)
+    }
+
+    fn visit_plain_paragraph(&mut self, node: &PlainParagraph) {
+        walk_plain_paragraph(self, node)
+    }
+
+    fn visit_heading(&mut self, node: &Heading) {
+        walk_heading(self, node)
+    }
+
+    fn visit_thematic_break(&mut self, _node: &ThematicBreak) {}
+
+    fn visit_ordered_list(&mut self, node: &OrderedList) {
+        walk_ordered_list(self, node)
+    }
+
+    fn visit_unordered_list(&mut self, node: &UnorderedList) {
+        walk_unordered_list(self, node)
+    }
+
+    fn visit_list_item(&mut self, node: &ListItem) {
+        walk_list_item(self, node)
+    }
+
+    fn visit_quote(&mut self, node: &Quote) {
+        walk_quote(self, node)
+    }
+
+    fn visit_code_block(&mut self, _node: &CodeBlock) {}
+
+    fn visit_html_block(&mut self, _node: &HtmlBlock) {}
+
+    fn visit_table(&mut self, node: &Table) {
+        walk_table(self, node)
+    }
+
+    fn visit_table_row(&mut self, node: &TableRow) {
+        walk_table_row(self, node)
+    }
+
+    fn visit_math_display(&mut self, _node: &MathDisplay) {}
+
+    fn visit_footnote(&mut self, node: &Footnote) {
+        walk_footnote(self, node)
+    }
+
+    fn visit_text(&mut self, node: &Text) {
+        walk_text(self, node)
+    }
+
+    fn visit_inline(&mut self, node: &Inline) {
+        walk_inline(self, node)
+    }
+
+    fn visit_plain(&mut self, _node: &Plain) {}
+
+    fn visit_bold(&mut self, node: &Bold) {
+        walk_bold(self, node)
+    }
+
+    fn visit_italic(&mut self, node: &Italic) {
+        walk_italic(self, node)
+    }
+
+    fn visit_deleted(&mut self, node: &Deleted) {
+        walk_deleted(self, node)
+    }
+
+    fn visit_code_inline(&mut self, _node: &CodeInline) {}
+
+    fn visit_math_inline(&mut self, _node: &MathInline) {}
+
+    fn visit_image(&mut self, _node: &Image) {}
+
+    fn visit_url(&mut self, node: &Url) {
+        walk_url(self, node)
+    }
+
+    fn visit_html_inline(&mut self, _node: &HtmlInline) {}
+
+    fn visit_soft_break(&mut self, _node: &SoftBreak) {}
+
+    fn visit_hard_break(&mut self, _node: &HardBreak) {}
+
+    fn visit_footnote_reference(&mut self, _node: &FootnoteReference) {}
+}
+
+pub fn walk_document<V: Visitor + ?Sized>(v: &mut V, node: &Document) {
+    for x in &node.blocks {
+        v.visit_block(x);
+    }
+    for x in &node.footnotes {
+        v.visit_footnote(x);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(v: &mut V, node: &Block) {
+    match node {
+        Block::PlainParagraph(x) => v.visit_plain_paragraph(x),
+        Block::Heading(x) => v.visit_heading(x),
+        Block::ThematicBreak(x) => v.visit_thematic_break(x),
+        Block::OrderedList(x) => v.visit_ordered_list(x),
+        Block::UnorderedList(x) => v.visit_unordered_list(x),
+        Block::Quote(x) => v.visit_quote(x),
+        Block::CodeBlock(x) => v.visit_code_block(x),
+        Block::HtmlBlock(x) => v.visit_html_block(x),
+        Block::Table(x) => v.visit_table(x),
+        Block::MathDisplay(x) => v.visit_math_display(x),
+    }
+}
+
+pub fn walk_plain_paragraph<V: Visitor + ?Sized>(v: &mut V, node: &PlainParagraph) {
+    v.visit_text(&node.text);
+}
+
+pub fn walk_heading<V: Visitor + ?Sized>(v: &mut V, node: &Heading) {
+    v.visit_text(&node.text);
+}
+
+pub fn walk_ordered_list<V: Visitor + ?Sized>(v: &mut V, node: &OrderedList) {
+    for x in &node.items {
+        v.visit_list_item(x);
+    }
+}
+
+pub fn walk_unordered_list<V: Visitor + ?Sized>(v: &mut V, node: &UnorderedList) {
+    for x in &node.items {
+        v.visit_list_item(x);
+    }
+}
+
+pub fn walk_list_item<V: Visitor + ?Sized>(v: &mut V, node: &ListItem) {
+    for x in &node.blocks {
+        v.visit_block(x);
+    }
+}
+
+pub fn walk_quote<V: Visitor + ?Sized>(v: &mut V, node: &Quote) {
+    for x in &node.blocks {
+        v.visit_block(x);
+    }
+}
+
+pub fn walk_table<V: Visitor + ?Sized>(v: &mut V, node: &Table) {
+    v.visit_table_row(&node.header);
+    for x in &node.rows {
+        v.visit_table_row(x);
+    }
+}
+
+pub fn walk_table_row<V: Visitor + ?Sized>(v: &mut V, node: &TableRow) {
+    for x in &node.cells {
+        v.visit_text(x);
+    }
+}
+
+pub fn walk_footnote<V: Visitor + ?Sized>(v: &mut V, node: &Footnote) {
+    for x in &node.blocks {
+        v.visit_block(x);
+    }
+}
+
+pub fn walk_text<V: Visitor + ?Sized>(v: &mut V, node: &Text) {
+    for x in &node.nodes {
+        v.visit_inline(x);
+    }
+}
+
+pub fn walk_inline<V: Visitor + ?Sized>(v: &mut V, node: &Inline) {
+    match node {
+        Inline::Plain(x) => v.visit_plain(x),
+        Inline::Bold(x) => v.visit_bold(x),
+        Inline::Italic(x) => v.visit_italic(x),
+        Inline::Deleted(x) => v.visit_deleted(x),
+        Inline::CodeInline(x) => v.visit_code_inline(x),
+        Inline::MathInline(x) => v.visit_math_inline(x),
+        Inline::Image(x) => v.visit_image(x),
+        Inline::Url(x) => v.visit_url(x),
+        Inline::HtmlInline(x) => v.visit_html_inline(x),
+        Inline::SoftBreak(x) => v.visit_soft_break(x),
+        Inline::HardBreak(x) => v.visit_hard_break(x),
+        Inline::FootnoteReference(x) => v.visit_footnote_reference(x),
+    }
+}
+
+pub fn walk_bold<V: Visitor + ?Sized>(v: &mut V, node: &Bold) {
+    v.visit_text(&node.text);
+}
+
+pub fn walk_italic<V: Visitor + ?Sized>(v: &mut V, node: &Italic) {
+    v.visit_text(&node.text);
+}
+
+pub fn walk_deleted<V: Visitor + ?Sized>(v: &mut V, node: &Deleted) {
+    v.visit_text(&node.text);
+}
+
+pub fn walk_url<V: Visitor + ?Sized>(v: &mut V, node: &Url) {
+    v.visit_text(&node.text);
+}
+
+/// Walks a document mutably; the mirror image of [`Visitor`].
+///
+/// Overridden methods may rewrite or drop nodes before (or instead of)
+/// walking into them with the matching `walk_*_mut` function.
+pub trait VisitorMut {
+    fn visit_document_mut(&mut self, node: &mut Document) {
+        walk_document_mut(self, node)
+    }
+
+    fn visit_block_mut(&mut self, node: &mut Block) {
+        walk_block_mut(self, node)
+    }
+
+    fn visit_plain_paragraph_mut(&mut self, node: &mut PlainParagraph) {
+        walk_plain_paragraph_mut(self, node)
+    }
+
+    fn visit_heading_mut(&mut self, node: &mut Heading) {
+        walk_heading_mut(self, node)
+    }
+
+    fn visit_thematic_break_mut(&mut self, _node: &mut ThematicBreak) {}
+
+    fn visit_ordered_list_mut(&mut self, node: &mut OrderedList) {
+        walk_ordered_list_mut(self, node)
+    }
+
+    fn visit_unordered_list_mut(&mut self, node: &mut UnorderedList) {
+        walk_unordered_list_mut(self, node)
+    }
+
+    fn visit_list_item_mut(&mut self, node: &mut ListItem) {
+        walk_list_item_mut(self, node)
+    }
+
+    fn visit_quote_mut(&mut self, node: &mut Quote) {
+        walk_quote_mut(self, node)
+    }
+
+    fn visit_code_block_mut(&mut self, _node: &mut CodeBlock) {}
+
+    fn visit_html_block_mut(&mut self, _node: &mut HtmlBlock) {}
+
+    fn visit_table_mut(&mut self, node: &mut Table) {
+        walk_table_mut(self, node)
+    }
+
+    fn visit_table_row_mut(&mut self, node: &mut TableRow) {
+        walk_table_row_mut(self, node)
+    }
+
+    fn visit_math_display_mut(&mut self, _node: &mut MathDisplay) {}
+
+    fn visit_footnote_mut(&mut self, node: &mut Footnote) {
+        walk_footnote_mut(self, node)
+    }
+
+    fn visit_text_mut(&mut self, node: &mut Text) {
+        walk_text_mut(self, node)
+    }
+
+    fn visit_inline_mut(&mut self, node: &mut Inline) {
+        walk_inline_mut(self, node)
+    }
+
+    fn visit_plain_mut(&mut self, _node: &mut Plain) {}
+
+    fn visit_bold_mut(&mut self, node: &mut Bold) {
+        walk_bold_mut(self, node)
+    }
+
+    fn visit_italic_mut(&mut self, node: &mut Italic) {
+        walk_italic_mut(self, node)
+    }
+
+    fn visit_deleted_mut(&mut self, node: &mut Deleted) {
+        walk_deleted_mut(self, node)
+    }
+
+    fn visit_code_inline_mut(&mut self, _node: &mut CodeInline) {}
+
+    fn visit_math_inline_mut(&mut self, _node: &mut MathInline) {}
+
+    fn visit_image_mut(&mut self, _node: &mut Image) {}
+
+    fn visit_url_mut(&mut self, node: &mut Url) {
+        walk_url_mut(self, node)
+    }
+
+    fn visit_html_inline_mut(&mut self, _node: &mut HtmlInline) {}
+
+    fn visit_soft_break_mut(&mut self, _node: &mut SoftBreak) {}
+
+    fn visit_hard_break_mut(&mut self, _node: &mut HardBreak) {}
+
+    fn visit_footnote_reference_mut(&mut self, _node: &mut FootnoteReference) {}
+}
+
+pub fn walk_document_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Document) {
+    for x in &mut node.blocks {
+        v.visit_block_mut(x);
+    }
+    for x in &mut node.footnotes {
+        v.visit_footnote_mut(x);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Block) {
+    match node {
+        Block::PlainParagraph(x) => v.visit_plain_paragraph_mut(x),
+        Block::Heading(x) => v.visit_heading_mut(x),
+        Block::ThematicBreak(x) => v.visit_thematic_break_mut(x),
+        Block::OrderedList(x) => v.visit_ordered_list_mut(x),
+        Block::UnorderedList(x) => v.visit_unordered_list_mut(x),
+        Block::Quote(x) => v.visit_quote_mut(x),
+        Block::CodeBlock(x) => v.visit_code_block_mut(x),
+        Block::HtmlBlock(x) => v.visit_html_block_mut(x),
+        Block::Table(x) => v.visit_table_mut(x),
+        Block::MathDisplay(x) => v.visit_math_display_mut(x),
+    }
+}
+
+pub fn walk_plain_paragraph_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut PlainParagraph) {
+    v.visit_text_mut(&mut node.text);
+}
+
+pub fn walk_heading_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Heading) {
+    v.visit_text_mut(&mut node.text);
+}
+
+pub fn walk_ordered_list_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut OrderedList) {
+    for x in &mut node.items {
+        v.visit_list_item_mut(x);
+    }
+}
+
+pub fn walk_unordered_list_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut UnorderedList) {
+    for x in &mut node.items {
+        v.visit_list_item_mut(x);
+    }
+}
+
+pub fn walk_list_item_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut ListItem) {
+    for x in &mut node.blocks {
+        v.visit_block_mut(x);
+    }
+}
+
+pub fn walk_quote_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Quote) {
+    for x in &mut node.blocks {
+        v.visit_block_mut(x);
+    }
+}
+
+pub fn walk_table_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Table) {
+    v.visit_table_row_mut(&mut node.header);
+    for x in &mut node.rows {
+        v.visit_table_row_mut(x);
+    }
+}
+
+pub fn walk_table_row_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut TableRow) {
+    for x in &mut node.cells {
+        v.visit_text_mut(x);
+    }
+}
+
+pub fn walk_footnote_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Footnote) {
+    for x in &mut node.blocks {
+        v.visit_block_mut(x);
+    }
+}
+
+pub fn walk_text_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Text) {
+    for x in &mut node.nodes {
+        v.visit_inline_mut(x);
+    }
+}
+
+pub fn walk_inline_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Inline) {
+    match node {
+        Inline::Plain(x) => v.visit_plain_mut(x),
+        Inline::Bold(x) => v.visit_bold_mut(x),
+        Inline::Italic(x) => v.visit_italic_mut(x),
+        Inline::Deleted(x) => v.visit_deleted_mut(x),
+        Inline::CodeInline(x) => v.visit_code_inline_mut(x),
+        Inline::MathInline(x) => v.visit_math_inline_mut(x),
+        Inline::Image(x) => v.visit_image_mut(x),
+        Inline::Url(x) => v.visit_url_mut(x),
+        Inline::HtmlInline(x) => v.visit_html_inline_mut(x),
+        Inline::SoftBreak(x) => v.visit_soft_break_mut(x),
+        Inline::HardBreak(x) => v.visit_hard_break_mut(x),
+        Inline::FootnoteReference(x) => v.visit_footnote_reference_mut(x),
+    }
+}
+
+pub fn walk_bold_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Bold) {
+    v.visit_text_mut(&mut node.text);
+}
+
+pub fn walk_italic_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Italic) {
+    v.visit_text_mut(&mut node.text);
+}
+
+pub fn walk_deleted_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Deleted) {
+    v.visit_text_mut(&mut node.text);
+}
+
+pub fn walk_url_mut<V: VisitorMut + ?Sized>(v: &mut V, node: &mut Url) {
+    v.visit_text_mut(&mut node.text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_headings() {
+        struct Headings(Vec<u8>);
+        impl Visitor for Headings {
+            fn visit_heading(&mut self, node: &Heading) {
+                self.0.push(node.rank);
+            }
+        }
+        let doc = crate::parse("# a\ntext\n### b\n").unwrap();
+        let mut v = Headings(vec![]);
+        v.visit_document(&doc);
+        assert_eq!(v.0, vec![1, 3]);
+    }
+
+    #[test]
+    fn rewrites_links_and_strips_images() {
+        struct Rewrite;
+        impl VisitorMut for Rewrite {
+            fn visit_text_mut(&mut self, node: &mut Text) {
+                node.nodes.retain(|x| !matches!(x, Inline::Image(_)));
+                walk_text_mut(self, node);
+            }
+            fn visit_url_mut(&mut self, node: &mut Url) {
+                node.url = format!("https://example.com/{}", node.url);
+            }
+        }
+        let mut doc = crate::parse("- **[a](b)** ![c](d)\n").unwrap();
+        Rewrite.visit_document_mut(&mut doc);
+        assert_eq!(
+            doc.to_html(),
+            "<ul>\n<li><strong><a href=\"https://example.com/b\">a</a></strong> </li>\n</ul>\n"
+        );
+    }
+}